@@ -13,7 +13,7 @@ pub enum Expr {
     String(Rc<String>),
     Array(Vec<Expr>),
     Dict(Vec<(CompactString, Expr)>),
-    Function(Vec<Symbol>, Box<Expr>),
+    Function(Params, Box<Expr>),
 
     Variable(Symbol),
 
@@ -22,10 +22,23 @@ pub enum Expr {
 
     If(Box<Expr>, Box<Expr>, Box<Expr>),
     Local(Symbol, Box<Expr>, Box<Expr>),
+    // `None` in the arm pattern stands for the wildcard `_`.
+    Switch(Box<Expr>, Vec<(Option<Expr>, Expr)>),
 
-    FunctionCall(Box<Expr>, Vec<Expr>),
+    FunctionCall(Box<Expr>, Vec<Arg>),
     FieldAccess(Box<Expr>, Symbol),
     IndexAccess(Box<Expr>, Box<Expr>),
+
+    Import(Rc<String>),
+
+    // Attaches the source location to an expression that can fail at runtime.
+    Located(Span, Box<Expr>),
+}
+
+impl Expr {
+    pub fn located(start: usize, end: usize, expr: Expr) -> Expr {
+        Expr::Located(Span { start, end }, Box::new(expr))
+    }
 }
 
 impl Debug for Expr {
@@ -73,6 +86,22 @@ impl Debug for Expr {
 
             Expr::If(cond, then, else_) => write!(f, "if {cond:?} then {then:?} else {else_:?}"),
             Expr::Local(name, expr1, expr2) => write!(f, "local {name} = {expr1:?};\n{expr2:?}"),
+            Expr::Switch(expr, arms) => {
+                write!(f, "switch {expr:?} {{")?;
+                let mut first = true;
+                for (pattern, body) in arms {
+                    if first {
+                        first = false;
+                    } else {
+                        write!(f, ", ")?;
+                    }
+                    match pattern {
+                        Some(pattern) => write!(f, "{pattern:?} => {body:?}")?,
+                        None => write!(f, "_ => {body:?}")?,
+                    }
+                }
+                write!(f, "}}")
+            }
 
             Expr::FunctionCall(func, args) => {
                 write!(f, "{func:?}(")?;
@@ -90,7 +119,64 @@ impl Debug for Expr {
 
             Expr::FieldAccess(expr, name) => write!(f, "{expr:?}.{name}"),
             Expr::IndexAccess(expr, index) => write!(f, "{expr:?}[{index:?}]"),
+
+            Expr::Import(name) => write!(f, "import {name:?}"),
+            Expr::Located(_, expr) => write!(f, "{expr:?}"),
+        }
+    }
+}
+
+// A range of bytes `start..end` in the source code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone)]
+pub enum Arg {
+    Positional(Expr),
+    Named(Symbol, Expr),
+    // `...expr` expands an array into positional arguments.
+    Spread(Expr),
+}
+
+impl Debug for Arg {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            Arg::Positional(expr) => write!(f, "{expr:?}"),
+            Arg::Named(name, expr) => write!(f, "{name}: {expr:?}"),
+            Arg::Spread(expr) => write!(f, "...{expr:?}"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Params {
+    pub fixed: Vec<Symbol>,
+    // The rest parameter, which receives the surplus arguments as an array.
+    pub rest: Option<Symbol>,
+}
+
+impl Debug for Params {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "[")?;
+        let mut first = true;
+        for param in &self.fixed {
+            if first {
+                first = false;
+            } else {
+                write!(f, ", ")?;
+            }
+            write!(f, "{param:?}")?;
+        }
+        if let Some(rest) = &self.rest {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "...{rest:?}")?;
         }
+        write!(f, "]")
     }
 }
 
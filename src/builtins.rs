@@ -0,0 +1,2227 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+
+use crate::dict::Dict;
+use crate::eval::{self, Env, EvalError};
+use crate::types::Erasure;
+use crate::value::{Memoized, OutputOptions, Thunk, Value};
+
+type BuiltinFn = fn(&Env, &[Rc<Thunk>]) -> eval::Result<Value>;
+
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub func: BuiltinFn,
+}
+
+impl Debug for Builtin {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<builtin {}>", self.name)
+    }
+}
+
+impl Builtin {
+    const fn new(name: &'static str, func: BuiltinFn) -> Self {
+        Self { name, func }
+    }
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin::new("random", random),
+    Builtin::new("randomInt", random_int),
+    Builtin::new("sum", sum),
+    Builtin::new("product", product),
+    Builtin::new("mapValues", map_values),
+    Builtin::new("mapKeys", map_keys),
+    Builtin::new("keys", keys),
+    Builtin::new("sortedKeys", sorted_keys),
+    Builtin::new("entries", entries),
+    Builtin::new("fromEntries", from_entries),
+    Builtin::new("pick", pick),
+    Builtin::new("omit", omit),
+    Builtin::new("map2", map2),
+    Builtin::new("takeWhile", take_while),
+    Builtin::new("dropWhile", drop_while),
+    Builtin::new("take", take),
+    Builtin::new("drop", drop),
+    Builtin::new("slice", slice),
+    Builtin::new("replicate", replicate),
+    Builtin::new("coalesce", coalesce),
+    Builtin::new("dedent", dedent),
+    Builtin::new("indent", indent),
+    Builtin::new("padStart", pad_start),
+    Builtin::new("padEnd", pad_end),
+    Builtin::new("all", all),
+    Builtin::new("any", any),
+    Builtin::new("count", count),
+    Builtin::new("find", find),
+    Builtin::new("findIndex", find_index),
+    Builtin::new("concat", concat),
+    Builtin::new("tabulate", tabulate),
+    Builtin::new("trace", trace),
+    Builtin::new("diff", diff),
+    Builtin::new("mergeAll", merge_all),
+    Builtin::new("isNull", is_null),
+    Builtin::new("isBool", is_bool),
+    Builtin::new("isNumber", is_number),
+    Builtin::new("isString", is_string),
+    Builtin::new("isArray", is_array),
+    Builtin::new("isDict", is_dict),
+    Builtin::new("isFunction", is_function),
+    Builtin::new("assertEq", assert_equal),
+    Builtin::new("memoize", memoize),
+    Builtin::new("withDefault", with_default),
+    Builtin::new("round", round),
+    Builtin::new("floor", floor),
+    Builtin::new("ceil", ceil),
+    Builtin::new("getPath", get_path),
+    Builtin::new("getPathOr", get_path_or),
+    Builtin::new("sort", sort),
+    Builtin::new("sortBy", sort_by),
+    Builtin::new("sortByDesc", sort_by_desc),
+    Builtin::new("invert", invert),
+];
+
+fn expect_args<const N: usize>(args: &[Rc<Thunk>]) -> eval::Result<&[Rc<Thunk>; N]> {
+    args.try_into()
+        .map_err(|_| EvalError::WrongNumberOfArguments {
+            expected: N,
+            actual: args.len(),
+        })
+}
+
+type ArgsWithOptional<'a, const N: usize> = (&'a [Rc<Thunk>; N], Option<&'a Rc<Thunk>>);
+
+// Splits `args` into `N` required arguments and one optional argument following them.
+fn expect_args_with_optional<const N: usize>(
+    args: &[Rc<Thunk>],
+) -> eval::Result<ArgsWithOptional<'_, N>> {
+    match args.len() {
+        n if n == N => Ok((expect_args(args)?, None)),
+        n if n == N + 1 => Ok((expect_args(&args[..N])?, Some(&args[N]))),
+        n => Err(EvalError::WrongNumberOfArguments {
+            expected: if n < N { N } else { N + 1 },
+            actual: n,
+        }),
+    }
+}
+
+fn expect_number(value: Value) -> eval::Result<f64> {
+    match value {
+        Value::Number(n) => Ok(n),
+        _ => Err(EvalError::BadOperandType {
+            expected: Erasure::Number.to_string(),
+            actual: value.erasure().to_string(),
+        }),
+    }
+}
+
+fn expect_array(value: Value) -> eval::Result<im_rc::Vector<Rc<Thunk>>> {
+    match value {
+        Value::Array(array) => Ok(array),
+        _ => Err(EvalError::BadOperandType {
+            expected: Erasure::Array.to_string(),
+            actual: value.erasure().to_string(),
+        }),
+    }
+}
+
+fn expect_bool(value: Value) -> eval::Result<bool> {
+    match value {
+        Value::Bool(b) => Ok(b),
+        _ => Err(EvalError::BadOperandType {
+            expected: Erasure::Bool.to_string(),
+            actual: value.erasure().to_string(),
+        }),
+    }
+}
+
+fn expect_string(value: Value) -> eval::Result<Rc<String>> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(EvalError::BadOperandType {
+            expected: Erasure::String.to_string(),
+            actual: value.erasure().to_string(),
+        }),
+    }
+}
+
+fn expect_dict(value: Value) -> eval::Result<Dict> {
+    match value {
+        Value::Dict(dict) => Ok(dict),
+        _ => Err(EvalError::BadOperandType {
+            expected: Erasure::Dict.to_string(),
+            actual: value.erasure().to_string(),
+        }),
+    }
+}
+
+fn expect_function(value: Value) -> eval::Result<Value> {
+    match value.erasure() {
+        Erasure::Function => Ok(value),
+        actual => Err(EvalError::BadOperandType {
+            expected: Erasure::Function.to_string(),
+            actual: actual.to_string(),
+        }),
+    }
+}
+
+fn expect_integer(value: Value) -> eval::Result<f64> {
+    let n = expect_number(value)?;
+    if n.fract() != 0.0 || !n.is_finite() {
+        return Err(EvalError::InvalidArgument(format!("{n} is not an integer")));
+    }
+    Ok(n)
+}
+
+// The upper bound of counts and widths, which keeps a typo like `replicate(1e12, x)`
+// from exhausting memory.
+const MAX_COUNT: usize = 10_000_000;
+
+fn expect_count(value: Value) -> eval::Result<usize> {
+    let n = expect_integer(value)?;
+    if n < 0.0 {
+        return Err(EvalError::InvalidArgument(format!(
+            "count must not be negative: {n}"
+        )));
+    }
+    if n > MAX_COUNT as f64 {
+        return Err(EvalError::InvalidArgument(format!(
+            "count must not exceed {MAX_COUNT}: {n}"
+        )));
+    }
+    Ok(n as usize)
+}
+
+// random() returns a number in [0, 1).
+// This is impure: the result depends on the state of the PRNG owned by the runtime,
+// which is seeded by `--seed` to make the output reproducible.
+fn random(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    expect_args::<0>(args)?;
+    let n = env.runtime().rng().next_f64();
+    Ok(Value::Number(n))
+}
+
+// randomInt(lo, hi) returns an integer in [lo, hi).
+// This is impure in the same way as `random`.
+// Both bounds must be within ±2^53, where every integer is exactly a number.
+fn random_int(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [lo, hi] = expect_args(args)?;
+    let lo = expect_integer(lo.force()?)?;
+    let hi = expect_integer(hi.force()?)?;
+    if lo >= hi {
+        return Err(EvalError::InvalidArgument(format!(
+            "empty range: [{lo}, {hi})"
+        )));
+    }
+    const MAX_BOUND: f64 = (1u64 << 53) as f64;
+    if lo.abs() > MAX_BOUND || hi.abs() > MAX_BOUND {
+        return Err(EvalError::InvalidArgument(format!(
+            "range is out of the exact integers: [{lo}, {hi})"
+        )));
+    }
+    let n = env.runtime().rng().next_below((hi - lo) as u64);
+    Ok(Value::Number(lo + n as f64))
+}
+
+// sum(array) returns the sum of the numbers in `array`. sum([]) is 0.
+fn sum(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [array] = expect_args(args)?;
+    let mut ret = 0.0;
+    for thunk in expect_array(array.force()?)? {
+        ret += expect_number(thunk.force()?)?;
+    }
+    Ok(Value::Number(ret))
+}
+
+// product(array) returns the product of the numbers in `array`. product([]) is 1.
+fn product(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [array] = expect_args(args)?;
+    let mut ret = 1.0;
+    for thunk in expect_array(array.force()?)? {
+        ret *= expect_number(thunk.force()?)?;
+    }
+    Ok(Value::Number(ret))
+}
+
+// mapValues(f, dict) applies `f` to every value of `dict` and keeps the keys.
+fn map_values(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [func, dict] = expect_args(args)?;
+    let func = expect_function(func.force()?)?;
+    let mut ret = Dict::new();
+    for (key, thunk) in expect_dict(dict.force()?)?.iter() {
+        let value = eval::call_function(env, &func, vec![Rc::clone(thunk)])?;
+        ret.insert(key.clone(), Rc::new(Thunk::from_value(value)));
+    }
+    Ok(Value::Dict(ret))
+}
+
+// mapKeys(f, d) returns a dict whose keys are `f` applied to the keys of `d`.
+// `f` must return a string. If two keys are mapped to the same key, the later one wins.
+fn map_keys(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [func, dict] = expect_args(args)?;
+    let func = expect_function(func.force()?)?;
+    let mut ret = Dict::new();
+    for (key, thunk) in expect_dict(dict.force()?)?.iter() {
+        let new_key = eval::call_function(env, &func, vec![string_thunk(key)])?;
+        let new_key = expect_string(new_key)?;
+        ret.insert(new_key.as_str().into(), Rc::clone(thunk));
+    }
+    Ok(Value::Dict(ret))
+}
+
+fn string_thunk(s: &str) -> Rc<Thunk> {
+    Rc::new(Thunk::from_value(Value::String(Rc::new(s.to_owned()))))
+}
+
+// keys(dict) returns the keys of `dict` in insertion order.
+fn keys(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [dict] = expect_args(args)?;
+    let dict = expect_dict(dict.force()?)?;
+    let ret = dict.iter().map(|(key, _)| string_thunk(key)).collect();
+    Ok(Value::Array(ret))
+}
+
+// sortedKeys(dict) returns the keys of `dict` in alphabetical order.
+fn sorted_keys(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [dict] = expect_args(args)?;
+    let dict = expect_dict(dict.force()?)?;
+    let ret = dict
+        .sorted()
+        .into_iter()
+        .map(|(key, _)| string_thunk(key))
+        .collect();
+    Ok(Value::Array(ret))
+}
+
+// entries(dict) returns an array of `[key, value]` pairs in the same order as the output,
+// which is sorted by key unless `--key-order` says otherwise. Use `keys` to visit the entries
+// in insertion order.
+fn entries(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [dict] = expect_args(args)?;
+    let dict = expect_dict(dict.force()?)?;
+    let ret = env
+        .runtime()
+        .key_order()
+        .arrange(&dict)
+        .into_iter()
+        .map(|(key, thunk)| {
+            let pair = im_rc::vector![string_thunk(key), Rc::clone(thunk)];
+            Rc::new(Thunk::from_value(Value::Array(pair)))
+        })
+        .collect();
+    Ok(Value::Array(ret))
+}
+
+// fromEntries(array) builds a dict from `[key, value]` pairs. A later pair wins over an earlier one.
+fn from_entries(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [array] = expect_args(args)?;
+    let mut ret = Dict::new();
+    for thunk in expect_array(array.force()?)? {
+        let pair = expect_array(thunk.force()?)?;
+        if pair.len() != 2 {
+            return Err(EvalError::InvalidArgument(format!(
+                "entry must be a [key, value] pair, but has {} elements",
+                pair.len()
+            )));
+        }
+        let key = expect_string(pair[0].force()?)?;
+        ret.insert(key.as_str().into(), Rc::clone(&pair[1]));
+    }
+    Ok(Value::Dict(ret))
+}
+
+// map2(f, a, b) applies `f` to the corresponding elements of `a` and `b`.
+// The result is as long as the shorter one.
+fn map2(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [func, a, b] = expect_args(args)?;
+    let func = expect_function(func.force()?)?;
+    let a = expect_array(a.force()?)?;
+    let b = expect_array(b.force()?)?;
+    let mut ret = im_rc::Vector::new();
+    for (x, y) in a.into_iter().zip(b) {
+        let value = eval::call_function(env, &func, vec![x, y])?;
+        ret.push_back(Rc::new(Thunk::from_value(value)));
+    }
+    Ok(Value::Array(ret))
+}
+
+fn expect_keys(value: Value) -> eval::Result<Vec<Rc<String>>> {
+    expect_array(value)?
+        .iter()
+        .map(|thunk| expect_string(thunk.force()?))
+        .collect()
+}
+
+// pick(keys, d) returns a dict with only the entries of `d` whose keys are in `keys`.
+// Keys which `d` does not have are ignored. The entries keep their order in `d`.
+fn pick(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [keys, dict] = expect_args(args)?;
+    let keys = expect_keys(keys.force()?)?;
+    let dict = expect_dict(dict.force()?)?;
+    let ret = dict
+        .iter()
+        .filter(|(key, _)| keys.iter().any(|k| k.as_str() == key.as_str()))
+        .map(|(key, thunk)| (key.clone(), Rc::clone(thunk)))
+        .collect();
+    Ok(Value::Dict(ret))
+}
+
+// omit(keys, d) returns `d` without the entries whose keys are in `keys`.
+fn omit(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [keys, dict] = expect_args(args)?;
+    let keys = expect_keys(keys.force()?)?;
+    let dict = expect_dict(dict.force()?)?;
+    let ret = dict
+        .iter()
+        .filter(|(key, _)| !keys.iter().any(|k| k.as_str() == key.as_str()))
+        .map(|(key, thunk)| (key.clone(), Rc::clone(thunk)))
+        .collect();
+    Ok(Value::Dict(ret))
+}
+
+// Returns the length of the leading run of `array` for which `pred` returns true.
+// Elements after the run are not forced.
+fn leading_run_len(
+    env: &Env,
+    pred: &Value,
+    array: &im_rc::Vector<Rc<Thunk>>,
+) -> eval::Result<usize> {
+    for (i, thunk) in array.iter().enumerate() {
+        let result = eval::call_function(env, pred, vec![Rc::clone(thunk)])?;
+        if !expect_bool(result)? {
+            return Ok(i);
+        }
+    }
+    Ok(array.len())
+}
+
+// takeWhile(pred, array) returns the leading elements of `array` for which `pred` returns true.
+fn take_while(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [pred, array] = expect_args(args)?;
+    let pred = expect_function(pred.force()?)?;
+    let array = expect_array(array.force()?)?;
+    let n = leading_run_len(env, &pred, &array)?;
+    Ok(Value::Array(array.take(n)))
+}
+
+// dropWhile(pred, array) returns the rest of `array` after `takeWhile(pred, array)`.
+fn drop_while(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [pred, array] = expect_args(args)?;
+    let pred = expect_function(pred.force()?)?;
+    let array = expect_array(array.force()?)?;
+    let n = leading_run_len(env, &pred, &array)?;
+    Ok(Value::Array(array.skip(n)))
+}
+
+// Clamps the index `n` into [0, len].
+// Negative indices are not counted from the end, unlike some languages; they are just 0.
+fn clamp_index(n: f64, len: usize) -> usize {
+    n.clamp(0.0, len as f64) as usize
+}
+
+// take(n, array) returns the first `n` elements of `array`.
+fn take(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [n, array] = expect_args(args)?;
+    let n = expect_integer(n.force()?)?;
+    let array = expect_array(array.force()?)?;
+    let n = clamp_index(n, array.len());
+    Ok(Value::Array(array.take(n)))
+}
+
+// drop(n, array) returns the elements of `array` after the first `n`.
+fn drop(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [n, array] = expect_args(args)?;
+    let n = expect_integer(n.force()?)?;
+    let array = expect_array(array.force()?)?;
+    let n = clamp_index(n, array.len());
+    Ok(Value::Array(array.skip(n)))
+}
+
+// slice(start, end, array) returns the elements of `array` in [start, end).
+// It is empty if `end` is not greater than `start`.
+fn slice(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [start, end, array] = expect_args(args)?;
+    let start = expect_integer(start.force()?)?;
+    let end = expect_integer(end.force()?)?;
+    let array = expect_array(array.force()?)?;
+    let start = clamp_index(start, array.len());
+    let end = clamp_index(end, array.len()).max(start);
+    Ok(Value::Array(array.skip(start).take(end - start)))
+}
+
+// replicate(n, x) returns an array of `n` elements, all of which are `x`.
+// `x` is evaluated at most once and shared by the elements.
+fn replicate(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [n, x] = expect_args(args)?;
+    let n = expect_count(n.force()?)?;
+    Ok(Value::Array(im_rc::Vector::from(vec![Rc::clone(x); n])))
+}
+
+// tabulate(n, f) returns [f(0), f(1), ..., f(n - 1)].
+fn tabulate(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [n, func] = expect_args(args)?;
+    let n = expect_count(n.force()?)?;
+    let func = expect_function(func.force()?)?;
+    let mut ret = im_rc::Vector::new();
+    for i in 0..n {
+        let index = Rc::new(Thunk::from_value(Value::Number(i as f64)));
+        let value = eval::call_function(env, &func, vec![index])?;
+        ret.push_back(Rc::new(Thunk::from_value(value)));
+    }
+    Ok(Value::Array(ret))
+}
+
+// coalesce(array) returns the first non-null element of `array`, or null if there is none.
+// Elements after the first non-null one are not forced.
+fn coalesce(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [array] = expect_args(args)?;
+    for thunk in expect_array(array.force()?)? {
+        let value = thunk.force()?;
+        if !matches!(value, Value::Null) {
+            return Ok(value);
+        }
+    }
+    Ok(Value::Null)
+}
+
+// dedent(s) removes the longest leading whitespace common to all lines of `s`.
+// Lines consisting only of whitespace are ignored when finding the common prefix,
+// and are made empty.
+fn dedent(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [s] = expect_args(args)?;
+    let s = expect_string(s.force()?)?;
+
+    let mut common: Option<&str> = None;
+    for line in s.split('\n').filter(|line| !line.trim().is_empty()) {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        common = Some(match common {
+            None => indent,
+            Some(prefix) => {
+                let len = prefix
+                    .char_indices()
+                    .zip(indent.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(prefix.len().min(indent.len()), |((i, _), _)| i);
+                &prefix[..len]
+            }
+        });
+    }
+    let common = common.unwrap_or("");
+
+    let lines: Vec<&str> = s
+        .split('\n')
+        .map(|line| match line.strip_prefix(common) {
+            Some(rest) if !line.trim().is_empty() => rest,
+            _ => "",
+        })
+        .collect();
+    Ok(Value::String(Rc::new(lines.join("\n"))))
+}
+
+// indent(n, s) prefixes each non-empty line of `s` with `n` spaces.
+fn indent(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [n, s] = expect_args(args)?;
+    let n = expect_count(n.force()?)?;
+    let s = expect_string(s.force()?)?;
+    let prefix = " ".repeat(n);
+    let lines: Vec<String> = s
+        .split('\n')
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{prefix}{line}")
+            }
+        })
+        .collect();
+    Ok(Value::String(Rc::new(lines.join("\n"))))
+}
+
+// all(pred, array) returns whether `pred` returns true for every element of `array`.
+// It stops at the first element for which `pred` returns false.
+fn all(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [pred, array] = expect_args(args)?;
+    let pred = expect_function(pred.force()?)?;
+    let array = expect_array(array.force()?)?;
+    let n = leading_run_len(env, &pred, &array)?;
+    Ok(Value::Bool(n == array.len()))
+}
+
+// any(pred, array) returns whether `pred` returns true for some element of `array`.
+// It stops at the first element for which `pred` returns true.
+fn any(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [pred, array] = expect_args(args)?;
+    let pred = expect_function(pred.force()?)?;
+    let array = expect_array(array.force()?)?;
+    Ok(Value::Bool(position(env, &pred, &array)?.is_some()))
+}
+
+// count(pred, array) returns the number of elements of `array` for which `pred` returns true.
+fn count(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [pred, array] = expect_args(args)?;
+    let pred = expect_function(pred.force()?)?;
+    let mut n = 0;
+    for thunk in expect_array(array.force()?)? {
+        if expect_bool(eval::call_function(env, &pred, vec![thunk])?)? {
+            n += 1;
+        }
+    }
+    Ok(Value::Number(n as f64))
+}
+
+// Returns the index of the first element of `array` for which `pred` returns true.
+// Elements after it are not forced.
+fn position(
+    env: &Env,
+    pred: &Value,
+    array: &im_rc::Vector<Rc<Thunk>>,
+) -> eval::Result<Option<usize>> {
+    for (i, thunk) in array.iter().enumerate() {
+        if expect_bool(eval::call_function(env, pred, vec![Rc::clone(thunk)])?)? {
+            return Ok(Some(i));
+        }
+    }
+    Ok(None)
+}
+
+// find(pred, array) returns the first element of `array` for which `pred` returns true,
+// or null if there is no such element.
+// Note that null is also returned when the found element itself is null;
+// use findIndex to tell the two cases apart.
+fn find(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [pred, array] = expect_args(args)?;
+    let pred = expect_function(pred.force()?)?;
+    let array = expect_array(array.force()?)?;
+    match position(env, &pred, &array)? {
+        Some(i) => array[i].force(),
+        None => Ok(Value::Null),
+    }
+}
+
+// findIndex(pred, array) returns the index of the first element of `array` for which
+// `pred` returns true, or -1 if there is no such element.
+fn find_index(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [pred, array] = expect_args(args)?;
+    let pred = expect_function(pred.force()?)?;
+    let array = expect_array(array.force()?)?;
+    let index = position(env, &pred, &array)?;
+    Ok(Value::Number(index.map_or(-1.0, |i| i as f64)))
+}
+
+// concat(a, b, ...) returns the elements of all the argument arrays in order.
+// It takes any number of arrays.
+fn concat(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let mut ret = im_rc::Vector::new();
+    for array in args {
+        ret.append(expect_array(array.force()?)?);
+    }
+    Ok(Value::Array(ret))
+}
+
+// Returns the padding needed to make `s` at least `width` characters wide,
+// made by repeating `fill` (which defaults to a space) and truncating it.
+fn padding(
+    s: &Rc<Thunk>,
+    width: &Rc<Thunk>,
+    fill: Option<&Rc<Thunk>>,
+) -> eval::Result<(Rc<String>, String)> {
+    let s = expect_string(s.force()?)?;
+    let width = expect_count(width.force()?)?;
+    let fill = match fill {
+        Some(fill) => expect_string(fill.force()?)?,
+        None => Rc::new(" ".to_owned()),
+    };
+    if fill.is_empty() {
+        return Err(EvalError::InvalidArgument(
+            "fill must not be empty".to_owned(),
+        ));
+    }
+    let len = s.chars().count();
+    let padding = fill
+        .chars()
+        .cycle()
+        .take(width.saturating_sub(len))
+        .collect();
+    Ok((s, padding))
+}
+
+// padStart(s, width, fill) pads `s` on the left with `fill` up to `width` characters.
+// `fill` is optional and defaults to a space. `s` is returned as is if it is already wide enough.
+fn pad_start(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let ([s, width], fill) = expect_args_with_optional(args)?;
+    let (s, padding) = padding(s, width, fill)?;
+    Ok(Value::String(Rc::new(padding + &s)))
+}
+
+// padEnd(s, width, fill) is the same as padStart, except that it pads `s` on the right.
+fn pad_end(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let ([s, width], fill) = expect_args_with_optional(args)?;
+    let (s, padding) = padding(s, width, fill)?;
+    Ok(Value::String(Rc::new(format!("{s}{padding}"))))
+}
+
+// trace(label, x) writes `label` and `x` to stderr and returns `x` as it is.
+// The message is written when the call is forced. Writing `x` forces nothing inside it,
+// so elements not evaluated yet at that time are written as `<unevaluated>`, and functions
+// as `<function>`. If `x` still cannot be written, the error is written instead.
+fn trace(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [label, x] = expect_args(args)?;
+    let label = expect_string(label.force()?)?;
+    let value = x.force()?;
+    let options = OutputOptions {
+        placeholders: true,
+        unevaluated_placeholder: true,
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&value.with_options(&options))
+        .unwrap_or_else(|e| format!("<error: {e}>"));
+    env.runtime().trace(&format!("{label}: {json}"));
+    Ok(value)
+}
+
+// diff(a, b) describes how `b` differs from `a`. The result is one of the following:
+//
+// - `{"op": "unchanged"}` if `a == b`.
+// - `{"op": "modified", "added": {...}, "removed": {...}, "changed": {...}}` if both are
+//   dicts which are not equal. `added` has the entries only in `b`, `removed` has the entries
+//   only in `a`, and `changed` maps each key whose value differs to the `diff` of the values.
+// - `{"op": "replaced", "from": a, "to": b}` otherwise. Arrays are not compared elementwise.
+//
+// Values are compared with `==`, so comparing functions is an error.
+fn diff(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [a, b] = expect_args(args)?;
+    diff_values(a, b)
+}
+
+fn diff_values(a: &Rc<Thunk>, b: &Rc<Thunk>) -> eval::Result<Value> {
+    let a_value = a.force()?;
+    let b_value = b.force()?;
+    let mut ret = Dict::new();
+    if Value::try_eq(&a_value, &b_value)? {
+        ret.insert("op".into(), string_thunk("unchanged"));
+        return Ok(Value::Dict(ret));
+    }
+    let (Value::Dict(a_dict), Value::Dict(b_dict)) = (a_value, b_value) else {
+        ret.insert("op".into(), string_thunk("replaced"));
+        ret.insert("from".into(), Rc::clone(a));
+        ret.insert("to".into(), Rc::clone(b));
+        return Ok(Value::Dict(ret));
+    };
+    let mut added = Dict::new();
+    let mut removed = Dict::new();
+    let mut changed = Dict::new();
+    for (key, a_thunk) in a_dict.iter() {
+        match b_dict.get(key) {
+            Some(b_thunk) => {
+                if !Value::try_eq(&a_thunk.force()?, &b_thunk.force()?)? {
+                    let d = diff_values(a_thunk, b_thunk)?;
+                    changed.insert(key.clone(), Rc::new(Thunk::from_value(d)));
+                }
+            }
+            None => removed.insert(key.clone(), Rc::clone(a_thunk)),
+        }
+    }
+    for (key, b_thunk) in b_dict.iter() {
+        if a_dict.get(key).is_none() {
+            added.insert(key.clone(), Rc::clone(b_thunk));
+        }
+    }
+    ret.insert("op".into(), string_thunk("modified"));
+    ret.insert(
+        "added".into(),
+        Rc::new(Thunk::from_value(Value::Dict(added))),
+    );
+    ret.insert(
+        "removed".into(),
+        Rc::new(Thunk::from_value(Value::Dict(removed))),
+    );
+    ret.insert(
+        "changed".into(),
+        Rc::new(Thunk::from_value(Value::Dict(changed))),
+    );
+    Ok(Value::Dict(ret))
+}
+
+// mergeAll(dicts) deep-merges the dicts in `dicts` from left to right, so later dicts override
+// earlier ones. mergeAll([]) is {}.
+fn merge_all(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [array] = expect_args(args)?;
+    let mut ret = Dict::new();
+    for thunk in expect_array(array.force()?)? {
+        ret = deep_merge(ret, expect_dict(thunk.force()?)?)?;
+    }
+    Ok(Value::Dict(ret))
+}
+
+// Merges `overrides` into `base`. If both have a dict under the same key, the two dicts are
+// merged recursively; otherwise the value in `overrides` wins.
+// Values under keys which only one side has are not forced.
+fn deep_merge(mut base: Dict, overrides: Dict) -> eval::Result<Dict> {
+    for (key, thunk) in overrides.iter() {
+        let merged = match base.get(key) {
+            Some(base_thunk) => match (base_thunk.force()?, thunk.force()?) {
+                (Value::Dict(d1), Value::Dict(d2)) => {
+                    Rc::new(Thunk::from_value(Value::Dict(deep_merge(d1, d2)?)))
+                }
+                _ => Rc::clone(thunk),
+            },
+            None => Rc::clone(thunk),
+        };
+        base.insert(key.clone(), merged);
+    }
+    Ok(base)
+}
+
+// isNull(x), isBool(x), ... return whether `x` is of the kind. Closures and builtins are both functions.
+fn has_erasure(args: &[Rc<Thunk>], erasure: Erasure) -> eval::Result<Value> {
+    let [x] = expect_args(args)?;
+    Ok(Value::Bool(x.force()?.erasure() == erasure))
+}
+
+fn is_null(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    has_erasure(args, Erasure::Null)
+}
+
+fn is_bool(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    has_erasure(args, Erasure::Bool)
+}
+
+fn is_number(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    has_erasure(args, Erasure::Number)
+}
+
+fn is_string(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    has_erasure(args, Erasure::String)
+}
+
+fn is_array(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    has_erasure(args, Erasure::Array)
+}
+
+fn is_dict(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    has_erasure(args, Erasure::Dict)
+}
+
+fn is_function(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    has_erasure(args, Erasure::Function)
+}
+
+// assertEq(actual, expected) returns `actual` if it equals `expected`, and fails otherwise.
+// The error shows both values in JSON.
+fn assert_equal(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [actual, expected] = expect_args(args)?;
+    let actual = actual.force()?;
+    let expected = expected.force()?;
+    if Value::try_eq(&actual, &expected)? {
+        return Ok(actual);
+    }
+    let options = OutputOptions {
+        placeholders: true,
+        ..Default::default()
+    };
+    let to_json = |value: &Value| {
+        serde_json::to_string(&value.with_options(&options))
+            .map_err(|e| EvalError::InvalidArgument(e.to_string()))
+    };
+    Err(EvalError::AssertionFailed {
+        expected: to_json(&expected)?,
+        actual: to_json(&actual)?,
+    })
+}
+
+// memoize(f) returns a function which behaves like `f` but remembers its results,
+// so that `f` is called only once for each list of arguments equal to each other.
+// Arguments are forced when the function is called, and they must not contain functions.
+fn memoize(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [func] = expect_args(args)?;
+    let func = expect_function(func.force()?)?;
+    Ok(Value::Memoized(Rc::new(Memoized::new(func))))
+}
+
+// withDefault(d, default) returns `d` in which accessing a missing field yields `default`
+// instead of failing. `default` does not appear in `keys` or in the output.
+// Builtins which build a new dict from `d`, such as `mapValues`, `pick` and `omit`,
+// do not keep `default`.
+fn with_default(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [dict, default] = expect_args(args)?;
+    let mut dict = expect_dict(dict.force()?)?;
+    dict.set_fallback(Rc::clone(default));
+    Ok(Value::Dict(dict))
+}
+
+// The largest `places` for which 10^places is finite.
+const MAX_PLACES: f64 = 308.0;
+
+// Applies `f` to the first argument scaled by 10^places, where `places` is the optional second
+// argument defaulting to 0. A negative `places` rounds to tens, hundreds and so on.
+// `places` is clamped to the range of finite powers of ten, and `x` is returned unchanged when
+// scaling it overflows, since such a number has no digits left to round at `places`.
+fn round_to_places(args: &[Rc<Thunk>], f: fn(f64) -> f64) -> eval::Result<Value> {
+    let ([x], places) = expect_args_with_optional::<1>(args)?;
+    let x = expect_number(x.force()?)?;
+    let places = match places {
+        Some(places) => expect_integer(places.force()?)?,
+        None => 0.0,
+    };
+    let places = places.clamp(-MAX_PLACES, MAX_PLACES);
+    let factor = 10f64.powi(places.abs() as i32);
+    let n = if places >= 0.0 {
+        let scaled = x * factor;
+        if !scaled.is_finite() {
+            return Ok(Value::Number(x));
+        }
+        f(scaled) / factor
+    } else {
+        f(x / factor) * factor
+    };
+    Ok(Value::Number(n))
+}
+
+// round(x, places) rounds `x` to `places` decimal places. Halves are rounded away from zero.
+fn round(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    round_to_places(args, f64::round)
+}
+
+// floor(x, places) rounds `x` toward negative infinity at `places` decimal places.
+fn floor(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    round_to_places(args, f64::floor)
+}
+
+// ceil(x, places) rounds `x` toward positive infinity at `places` decimal places.
+fn ceil(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    round_to_places(args, f64::ceil)
+}
+
+enum PathSegment {
+    Key(Rc<String>),
+    Index(f64),
+}
+
+impl PathSegment {
+    // Formats the segment as it is written in an access expression: `.key` or `[0]`.
+    fn display(&self, is_first: bool) -> String {
+        match self {
+            PathSegment::Key(key) if is_first => key.to_string(),
+            PathSegment::Key(key) => format!(".{key}"),
+            PathSegment::Index(i) => format!("[{i}]"),
+        }
+    }
+}
+
+fn expect_path(value: Value) -> eval::Result<Vec<PathSegment>> {
+    let mut segments = Vec::new();
+    for thunk in expect_array(value)? {
+        let segment = match thunk.force()? {
+            Value::String(key) => PathSegment::Key(key),
+            Value::Number(i) => PathSegment::Index(expect_integer(Value::Number(i))?),
+            value => {
+                return Err(EvalError::BadOperandType {
+                    expected: format!("{} or {}", Erasure::String, Erasure::Number),
+                    actual: value.erasure().to_string(),
+                })
+            }
+        };
+        segments.push(segment);
+    }
+    Ok(segments)
+}
+
+// Follows `path` from `value` in the same way as `value.key` and `value[i]`.
+// A missing key, an index out of bounds or a value which cannot be accessed by the segment
+// is reported as `PathNotFound` with the whole path and the failing segment.
+fn follow_path(mut value: Value, path: &[PathSegment]) -> eval::Result<Value> {
+    for segment in path {
+        let reason = match (&value, segment) {
+            (Value::Dict(dict), PathSegment::Key(key)) => match dict.get(key).or(dict.fallback()) {
+                Some(thunk) => {
+                    value = thunk.force()?;
+                    continue;
+                }
+                None => "field does not exist".to_owned(),
+            },
+            (Value::Array(array), PathSegment::Index(index)) => {
+                match array.get(*index as usize).filter(|_| *index >= 0.0) {
+                    Some(thunk) => {
+                        value = thunk.force()?;
+                        continue;
+                    }
+                    None => "index out of bounds".to_owned(),
+                }
+            }
+            (Value::String(s), PathSegment::Index(index)) => {
+                match s.chars().nth(*index as usize).filter(|_| *index >= 0.0) {
+                    Some(c) => {
+                        value = Value::String(Rc::new(String::from(c)));
+                        continue;
+                    }
+                    None => "index out of bounds".to_owned(),
+                }
+            }
+            (value, _) => format!("cannot access {}", value.erasure()),
+        };
+        let path: String = path
+            .iter()
+            .enumerate()
+            .map(|(j, segment)| segment.display(j == 0))
+            .collect();
+        return Err(EvalError::PathNotFound {
+            path,
+            segment: segment.display(true),
+            reason,
+        });
+    }
+    Ok(value)
+}
+
+// getPath(value, path) returns the value at `path`, which is an array of keys and indices.
+// For example, `getPath(x, ["a", 0, "b"])` is the same as `x.a[0].b`.
+fn get_path(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [value, path] = expect_args(args)?;
+    let path = expect_path(path.force()?)?;
+    follow_path(value.force()?, &path)
+}
+
+// getPathOr(value, path, default) is the same as `getPath(value, path)`,
+// but returns `default` if nothing is at `path`.
+fn get_path_or(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [value, path, default] = expect_args(args)?;
+    let path = expect_path(path.force()?)?;
+    match follow_path(value.force()?, &path) {
+        Err(EvalError::PathNotFound { .. }) => default.force(),
+        result => result,
+    }
+}
+
+// Sorts `array` by `keys`, which has a key for each element.
+// The sort is stable: elements with equal keys keep their order in `array`,
+// also when `descending` is true.
+fn sort_by_keys(
+    array: im_rc::Vector<Rc<Thunk>>,
+    keys: Vec<Value>,
+    descending: bool,
+) -> eval::Result<Value> {
+    // Checking every key against the first one up front ensures that the keys are all numbers
+    // without NaN or all strings. Otherwise the comparator would not be a total order.
+    if let Some(first) = keys.first() {
+        for key in &keys {
+            Value::try_cmp(first, key)?;
+        }
+    }
+    let mut indices: Vec<usize> = (0..array.len()).collect();
+    indices.sort_by(|&i, &j| {
+        let ordering = Value::try_cmp(&keys[i], &keys[j]).unwrap_or(Ordering::Equal);
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    Ok(Value::Array(
+        indices.into_iter().map(|i| Rc::clone(&array[i])).collect(),
+    ))
+}
+
+fn sort_keys_by(
+    env: &Env,
+    func: &Rc<Thunk>,
+    array: &im_rc::Vector<Rc<Thunk>>,
+) -> eval::Result<Vec<Value>> {
+    let func = expect_function(func.force()?)?;
+    array
+        .iter()
+        .map(|thunk| eval::call_function(env, &func, vec![Rc::clone(thunk)]))
+        .collect()
+}
+
+// sort(array) sorts numbers or strings in ascending order.
+fn sort(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [array] = expect_args(args)?;
+    let array = expect_array(array.force()?)?;
+    let keys = array
+        .iter()
+        .map(|thunk| thunk.force())
+        .collect::<eval::Result<_>>()?;
+    sort_by_keys(array, keys, false)
+}
+
+// sortBy(f, array) sorts `array` in ascending order of `f(x)`, which must be a number or a string.
+// Elements with equal keys keep their order, so the output does not change from run to run.
+fn sort_by(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [func, array] = expect_args(args)?;
+    let array = expect_array(array.force()?)?;
+    let keys = sort_keys_by(env, func, &array)?;
+    sort_by_keys(array, keys, false)
+}
+
+// sortByDesc(f, array) is the same as `sortBy` but in descending order.
+// Elements with equal keys still keep their order; it is not the reverse of `sortBy`.
+fn sort_by_desc(env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [func, array] = expect_args(args)?;
+    let array = expect_array(array.force()?)?;
+    let keys = sort_keys_by(env, func, &array)?;
+    sort_by_keys(array, keys, true)
+}
+
+// invert(d) swaps the keys and the values of `d`, whose values must be strings.
+// If two keys have the same value, the later one in insertion order wins, like `fromEntries`.
+fn invert(_env: &Env, args: &[Rc<Thunk>]) -> eval::Result<Value> {
+    let [dict] = expect_args(args)?;
+    let mut ret = Dict::new();
+    for (key, thunk) in expect_dict(dict.force()?)?.iter() {
+        let value = expect_string(thunk.force()?)?;
+        ret.insert(value.as_str().into(), string_thunk(key));
+    }
+    Ok(Value::Dict(ret))
+}
+
+#[test]
+fn random_test() {
+    use crate::eval::{eval_source_with_options, Options};
+
+    let source = "[random(), random(), randomInt(0, 1000), randomInt(-5, 5)]";
+    let run = |seed: u64| {
+        let options = Options {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let value = eval_source_with_options(options, source).unwrap();
+        serde_json::to_string(&value).unwrap()
+    };
+    assert_eq!(run(42), run(42));
+    assert_ne!(run(42), run(43));
+
+    let value = eval_source_with_options(Options::default(), "randomInt(3, 4)").unwrap();
+    assert!(matches!(value, Value::Number(n) if n == 3.0));
+
+    let value = eval_source_with_options(Options::default(), "random()").unwrap();
+    assert!(matches!(value, Value::Number(n) if (0.0..1.0).contains(&n)));
+
+    // Bounds up to ±2^53 are allowed, and the result never reaches `hi`.
+    let value = eval_source_with_options(
+        Options::default(),
+        "randomInt(-9007199254740992, 9007199254740992)",
+    );
+    assert!(matches!(value, Ok(Value::Number(n)) if n.abs() < 9007199254740992.0));
+    for seed in 0..10 {
+        let options = Options {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let value =
+            eval_source_with_options(options, "randomInt(9007199254740991, 9007199254740992)");
+        assert!(matches!(value, Ok(Value::Number(n)) if n == 9007199254740991.0));
+    }
+
+    for source in [
+        "randomInt(0.5, 2)",
+        "randomInt(0, 2.5)",
+        "randomInt(2, 2)",
+        "randomInt(0, 9007199254740994)",
+        "randomInt(-9007199254740994, 0)",
+        "randomInt(1152921504606846976, 1152921504606848000)",
+        "randomInt(0, 1e300)",
+    ] {
+        assert!(matches!(
+            eval_source_with_options(Options::default(), source),
+            Err(EvalError::InvalidArgument(_))
+        ));
+    }
+}
+
+#[test]
+fn sum_and_product_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(eval_to_json("sum([1, 2, 3.5])"), Ok("6.5".to_owned()));
+    assert_eq!(eval_to_json("product([2, 3, 4])"), Ok("24.0".to_owned()));
+    assert_eq!(eval_to_json("sum([])"), Ok("0.0".to_owned()));
+    assert_eq!(eval_to_json("product([])"), Ok("1.0".to_owned()));
+
+    assert_eq!(
+        eval_to_json("sum([1, \"2\"])"),
+        Err("bad operand type: expected=Number, actual=String".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("product(1)"),
+        Err("bad operand type: expected=Array, actual=Number".to_owned())
+    );
+}
+
+#[test]
+fn map_values_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("mapValues(function(v) v * 2, {a: 1, b: 2})"),
+        Ok(r#"{"a":2.0,"b":4.0}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("mapValues(function(v) v, {})"),
+        Ok("{}".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("mapValues(function(v) v, [1])"),
+        Err("bad operand type: expected=Dict, actual=Array".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("mapValues(1, {a: 1})"),
+        Err("bad operand type: expected=Function, actual=Number".to_owned())
+    );
+}
+
+#[test]
+fn entries_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("entries({b: 2, a: 1})"),
+        Ok(r#"[["a",1.0],["b",2.0]]"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("fromEntries([[\"a\", 1], [\"b\", 2], [\"a\", 3]])"),
+        Ok(r#"{"a":3.0,"b":2.0}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("local d = {a: 1, b: [2, {c: 3}]}; fromEntries(entries(d)) == d"),
+        Ok("true".to_owned())
+    );
+    // `entries` follows the order of the output, which `--key-order` can change.
+    let entries_with_order = |key_order: &str| {
+        use crate::eval::{eval_source_with_options, Options};
+        let options = Options {
+            key_order: key_order.parse().unwrap(),
+            ..Default::default()
+        };
+        let value = eval_source_with_options(options, "entries({b: 1, c: 2, a: 3})").unwrap();
+        serde_json::to_string(&value).unwrap()
+    };
+    assert_eq!(
+        entries_with_order("sorted"),
+        r#"[["a",3.0],["b",1.0],["c",2.0]]"#
+    );
+    assert_eq!(
+        entries_with_order("insertion"),
+        r#"[["b",1.0],["c",2.0],["a",3.0]]"#
+    );
+    assert_eq!(
+        entries_with_order("c,*"),
+        r#"[["c",2.0],["a",3.0],["b",1.0]]"#
+    );
+    assert_eq!(
+        eval_to_json("fromEntries(entries({}))"),
+        Ok("{}".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("fromEntries([[1, 2]])"),
+        Err("bad operand type: expected=String, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("fromEntries([[\"a\"]])"),
+        Err("invalid argument: entry must be a [key, value] pair, but has 1 elements".to_owned())
+    );
+}
+
+#[test]
+fn map2_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("map2(function(x, y) x + y, [1, 2, 3], [10, 20, 30])"),
+        Ok("[11.0,22.0,33.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("map2(function(x, y) [x, y], [1, 2, 3], [\"a\"])"),
+        Ok(r#"[[1.0,"a"]]"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("map2(function(x, y) x, [], [1])"),
+        Ok("[]".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("map2(function(x, y) x, [1], {})"),
+        Err("bad operand type: expected=Array, actual=Dict".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("map2(null, [1], [2])"),
+        Err("bad operand type: expected=Function, actual=Null".to_owned())
+    );
+}
+
+#[test]
+fn take_while_and_drop_while_test() {
+    use crate::eval::eval_to_json;
+
+    let source = |func: &str| format!("{func}(function(x) x != 0, [3, 2, 0, 1, 0])");
+    assert_eq!(
+        eval_to_json(&source("takeWhile")),
+        Ok("[3.0,2.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&source("dropWhile")),
+        Ok("[0.0,1.0,0.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("takeWhile(function(x) true, [])"),
+        Ok("[]".to_owned())
+    );
+
+    // Elements after the boundary are not forced.
+    assert_eq!(
+        eval_to_json("local bottom = bottom; takeWhile(function(x) x == 1, [1, 2, bottom])"),
+        Ok("[1.0]".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("dropWhile(function(x) x, [1])"),
+        Err("bad operand type: expected=Bool, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("takeWhile(function(x) true, \"abc\")"),
+        Err("bad operand type: expected=Array, actual=String".to_owned())
+    );
+}
+
+#[test]
+fn take_drop_slice_test() {
+    use crate::eval::eval_to_json;
+
+    let verify = |source: &str, expected: &str| {
+        assert_eq!(eval_to_json(source), Ok(expected.to_owned()));
+    };
+
+    verify("take(2, [1, 2, 3])", "[1.0,2.0]");
+    verify("drop(1, [1, 2, 3])", "[2.0,3.0]");
+    verify("slice(1, 3, [1, 2, 3, 4])", "[2.0,3.0]");
+
+    // Out-of-range indices are clamped into [0, length].
+    verify("take(5, [1, 2, 3])", "[1.0,2.0,3.0]");
+    verify("take(-1, [1, 2, 3])", "[]");
+    verify("drop(5, [1, 2, 3])", "[]");
+    verify("drop(-1, [1, 2, 3])", "[1.0,2.0,3.0]");
+    verify("slice(-2, 10, [1, 2, 3])", "[1.0,2.0,3.0]");
+    verify("slice(2, 1, [1, 2, 3])", "[]");
+
+    assert_eq!(
+        eval_to_json("take(1, {})"),
+        Err("bad operand type: expected=Array, actual=Dict".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("drop(0.5, [1])"),
+        Err("invalid argument: 0.5 is not an integer".to_owned())
+    );
+}
+
+#[test]
+fn replicate_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("replicate(3, \"x\")"),
+        Ok(r#"["x","x","x"]"#.to_owned())
+    );
+    assert_eq!(eval_to_json("replicate(0, 1)"), Ok("[]".to_owned()));
+    assert_eq!(
+        eval_to_json("local bottom = bottom; replicate(0, bottom)"),
+        Ok("[]".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("replicate(-1, 1)"),
+        Err("invalid argument: count must not be negative: -1".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("replicate(1.5, 1)"),
+        Err("invalid argument: 1.5 is not an integer".to_owned())
+    );
+
+    // Huge counts are rejected before allocating anything.
+    for source in [
+        "replicate(1e19, 1)",
+        "replicate(1e12, 1)",
+        "tabulate(1e12, function(i) i)",
+        r#"padStart("x", 1e12)"#,
+        r#"padEnd("x", 1e19, "ab")"#,
+        r#"indent(1e12, "x")"#,
+    ] {
+        assert_eq!(
+            eval_to_json(source)
+                .map_err(|e| e.starts_with("invalid argument: count must not exceed 10000000")),
+            Err(true),
+            "{source}"
+        );
+    }
+    assert_eq!(
+        eval_to_json("replicate(10000001, 1)"),
+        Err("invalid argument: count must not exceed 10000000: 10000001".to_owned())
+    );
+}
+
+#[test]
+fn keys_test() {
+    use crate::eval::eval_to_json;
+
+    let d = "{b: 1, c: 2, a: 3, b: 4}";
+    assert_eq!(
+        eval_to_json(&format!("keys({d})")),
+        Ok(r#"["b","c","a"]"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!("sortedKeys({d})")),
+        Ok(r#"["a","b","c"]"#.to_owned())
+    );
+    assert_eq!(eval_to_json("keys({})"), Ok("[]".to_owned()));
+}
+
+#[test]
+fn coalesce_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("coalesce([null, null, \"a\", null])"),
+        Ok(r#""a""#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("coalesce([null, null])"),
+        Ok("null".to_owned())
+    );
+    assert_eq!(eval_to_json("coalesce([])"), Ok("null".to_owned()));
+    assert_eq!(eval_to_json("coalesce([false, 1])"), Ok("false".to_owned()));
+
+    // The last element would be an error if it were forced.
+    assert_eq!(
+        eval_to_json("coalesce([null, 1, 1 + \"x\"])"),
+        Ok("1.0".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("coalesce(null)"),
+        Err("bad operand type: expected=Array, actual=Null".to_owned())
+    );
+}
+
+#[test]
+fn dedent_and_indent_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json(r#"dedent("    a:\n      b: 1\n\n    c: 2\n")"#),
+        Ok(r#""a:\n  b: 1\n\nc: 2\n""#.to_owned())
+    );
+    // Tabs and spaces are not interchangeable; only the common prefix is removed.
+    assert_eq!(
+        eval_to_json(r#"dedent("\t  x\n\t y\n   \n\t  z")"#),
+        Ok(r#"" x\ny\n\n z""#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"dedent("x\n  y")"#),
+        Ok(r#""x\n  y""#.to_owned())
+    );
+    assert_eq!(eval_to_json(r#"dedent("")"#), Ok(r#""""#.to_owned()));
+    assert_eq!(
+        eval_to_json("dedent(\"\"\"\n    SELECT *\n      FROM t\n    \"\"\")"),
+        Ok(r#""\nSELECT *\n  FROM t\n""#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(r#"indent(2, "a\n  b\n\nc\n")"#),
+        Ok(r#""  a\n    b\n\n  c\n""#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"indent(4, dedent("  a\n    b"))"#),
+        Ok(r#""    a\n      b""#.to_owned())
+    );
+    assert_eq!(eval_to_json(r#"indent(0, "a")"#), Ok(r#""a""#.to_owned()));
+
+    assert_eq!(
+        eval_to_json("dedent(1)"),
+        Err("bad operand type: expected=String, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"indent(-1, "a")"#),
+        Err("invalid argument: count must not be negative: -1".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("indent(2, [])"),
+        Err("bad operand type: expected=String, actual=Array".to_owned())
+    );
+}
+
+#[test]
+fn pad_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json(r#"padStart("42", 5, "0")"#),
+        Ok(r#""00042""#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"padEnd("ab", 5, ".")"#),
+        Ok(r#""ab...""#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"[padStart("x", 3), padEnd("x", 3)]"#),
+        Ok(r#"["  x","x  "]"#.to_owned())
+    );
+    // A multi-character fill is repeated and truncated.
+    assert_eq!(
+        eval_to_json(r#"padStart("x", 6, "ab")"#),
+        Ok(r#""ababax""#.to_owned())
+    );
+    // The width is counted in characters.
+    assert_eq!(
+        eval_to_json(r#"padEnd("αβ", 3, "-")"#),
+        Ok(r#""αβ-""#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(r#"[padStart("hello", 3, "*"), padEnd("hello", 5, "*"), padEnd("", 0)]"#),
+        Ok(r#"["hello","hello",""]"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(r#"padStart("x", -1)"#),
+        Err("invalid argument: count must not be negative: -1".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"padStart("x", 2.5)"#),
+        Err("invalid argument: 2.5 is not an integer".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"padEnd("x", 3, "")"#),
+        Err("invalid argument: fill must not be empty".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("padEnd(1, 3)"),
+        Err("bad operand type: expected=String, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"padStart("x")"#),
+        Err("wrong number of arguments: expected 2, got 1".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"padStart("x", 1, " ", " ")"#),
+        Err("wrong number of arguments: expected 3, got 4".to_owned())
+    );
+}
+
+#[test]
+fn all_and_any_test() {
+    use crate::eval::eval_to_json;
+
+    let even = "function(x) x % 2 == 0";
+    assert_eq!(
+        eval_to_json(&format!("[all({even}, [2, 4]), all({even}, [2, 3])]")),
+        Ok("[true,false]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!("[any({even}, [1, 2]), any({even}, [1, 3])]")),
+        Ok("[true,false]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!("[all({even}, []), any({even}, [])]")),
+        Ok("[true,false]".to_owned())
+    );
+
+    // Elements after the deciding one are neither forced nor passed to the predicate.
+    assert_eq!(
+        eval_to_json(&format!("all({even}, [2, 1, 1 + \"x\", \"y\"])")),
+        Ok("false".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!("any({even}, [1, 2, 1 + \"x\", \"y\"])")),
+        Ok("true".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("all(function(x) x, [true, 1])"),
+        Err("bad operand type: expected=Bool, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("any(function(x) null, [1])"),
+        Err("bad operand type: expected=Bool, actual=Null".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("any(function(x) true, {})"),
+        Err("bad operand type: expected=Array, actual=Dict".to_owned())
+    );
+}
+
+#[test]
+fn count_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("count(function(x) x % 2 == 0, [1, 2, 3, 4, 6])"),
+        Ok("3.0".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"count(function(x) x == "a", ["b"])"#),
+        Ok("0.0".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("count(function(x) true, [])"),
+        Ok("0.0".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("count(function(x) x, [true, \"yes\"])"),
+        Err("bad operand type: expected=Bool, actual=String".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("count(function(x) true, \"abc\")"),
+        Err("bad operand type: expected=Array, actual=String".to_owned())
+    );
+}
+
+#[test]
+fn find_test() {
+    use crate::eval::eval_to_json;
+
+    let even = "function(x) x % 2 == 0";
+    assert_eq!(
+        eval_to_json(&format!(
+            "[find({even}, [1, 4, 6]), findIndex({even}, [1, 4, 6])]"
+        )),
+        Ok("[4.0,1.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!(
+            "[find({even}, [1, 3]), findIndex({even}, [1, 3])]"
+        )),
+        Ok("[null,-1.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!("[find({even}, []), findIndex({even}, [])]")),
+        Ok("[null,-1.0]".to_owned())
+    );
+
+    // Elements after the match are not forced.
+    assert_eq!(
+        eval_to_json(&format!("findIndex({even}, [1, 2, 1 + \"x\"])")),
+        Ok("1.0".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("find(function(x) x, [1])"),
+        Err("bad operand type: expected=Bool, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("findIndex(1, [1])"),
+        Err("bad operand type: expected=Function, actual=Number".to_owned())
+    );
+}
+
+#[test]
+fn concat_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("concat([1, 2], [3])"),
+        Ok("[1.0,2.0,3.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"concat(["a"], [], ["b", "c"], ["d"])"#),
+        Ok(r#"["a","b","c","d"]"#.to_owned())
+    );
+    assert_eq!(eval_to_json("concat([1])"), Ok("[1.0]".to_owned()));
+    assert_eq!(eval_to_json("concat()"), Ok("[]".to_owned()));
+    assert_eq!(
+        eval_to_json("concat(...[[1], [2]])"),
+        Ok("[1.0,2.0]".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("concat([1], {})"),
+        Err("bad operand type: expected=Array, actual=Dict".to_owned())
+    );
+}
+
+#[test]
+fn tabulate_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("tabulate(3, function(i) i * i)"),
+        Ok("[0.0,1.0,4.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"tabulate(2, function(i) {id: i, name: "user" + "s"})"#),
+        Ok(r#"[{"id":0.0,"name":"users"},{"id":1.0,"name":"users"}]"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("tabulate(0, 1)"),
+        Err("bad operand type: expected=Function, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("tabulate(0, function(i) i)"),
+        Ok("[]".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("tabulate(-1, function(i) i)"),
+        Err("invalid argument: count must not be negative: -1".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("tabulate(1.5, function(i) i)"),
+        Err("invalid argument: 1.5 is not an integer".to_owned())
+    );
+}
+
+#[test]
+fn map_keys_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json(r#"mapKeys(function(k) "x_" + k, {a: 1, b: 2})"#),
+        Ok(r#"{"x_a":1.0,"x_b":2.0}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"keys(mapKeys(function(k) k + "!", {b: 1, a: 2}))"#),
+        Ok(r#"["b!","a!"]"#.to_owned())
+    );
+
+    // Both `a` and `b` are renamed to `c`; the later one wins.
+    let rename = r#"function(k) switch k { "a" => "c", "b" => "c", _ => k }"#;
+    assert_eq!(
+        eval_to_json(&format!("mapKeys({rename}, {{a: 1, d: 4, b: 2}})")),
+        Ok(r#"{"c":2.0,"d":4.0}"#.to_owned())
+    );
+
+    // The values are not forced.
+    assert_eq!(
+        eval_to_json(r#"keys(mapKeys(function(k) k, {a: 1 + "x"}))"#),
+        Ok(r#"["a"]"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("mapKeys(function(k) 1, {a: 1})"),
+        Err("bad operand type: expected=String, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("mapKeys(function(k) k, [1])"),
+        Err("bad operand type: expected=Dict, actual=Array".to_owned())
+    );
+}
+
+#[test]
+fn pick_and_omit_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json(r#"pick(["a"], {"a": 1, "b": 2})"#),
+        Ok(r#"{"a":1.0}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"omit(["a"], {"a": 1, "b": 2})"#),
+        Ok(r#"{"b":2.0}"#.to_owned())
+    );
+
+    // Missing keys are skipped.
+    assert_eq!(
+        eval_to_json(r#"pick(["c", "a", "x"], {a: 1, b: 2, c: 3})"#),
+        Ok(r#"{"a":1.0,"c":3.0}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"omit(["x"], {a: 1})"#),
+        Ok(r#"{"a":1.0}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"keys(pick(["c", "a"], {c: 1, b: 2, a: 3}))"#),
+        Ok(r#"["c","a"]"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"keys(omit(["b"], {c: 1, b: 2, a: 3}))"#),
+        Ok(r#"["c","a"]"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(r#"pick(["a", 1], {a: 1})"#),
+        Err("bad operand type: expected=String, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"omit(["a"], [])"#),
+        Err("bad operand type: expected=Dict, actual=Array".to_owned())
+    );
+}
+
+#[test]
+fn trace_test() {
+    use std::cell::RefCell;
+
+    use crate::eval::{eval_source_with_options, Diagnostics, Options};
+
+    let messages = Rc::new(RefCell::new(Vec::new()));
+    let options = Options {
+        diagnostics: Diagnostics::Capture(Rc::clone(&messages)),
+        ..Default::default()
+    };
+    let source = r#"
+        local x = trace("x", {a: [1, "b"], f: function(y) y});
+        local unused = trace("unused", 2);
+        local xs = [1, 2, 3];
+        [x.a, trace("sum", 1 + 2), if xs[0] + xs[2] == 4 then trace("xs", xs) else null]
+    "#;
+    let value = eval_source_with_options(options, source).unwrap();
+    assert_eq!(
+        serde_json::to_string(&value).unwrap(),
+        r#"[[1.0,"b"],3.0,[1.0,2.0,3.0]]"#
+    );
+    // Only the forced calls are reported, in the order in which they are forced.
+    // Only the elements evaluated before the call are written.
+    assert_eq!(
+        *messages.borrow(),
+        vec![
+            r#"trace: x: {"a":"<unevaluated>","f":"<unevaluated>"}"#.to_owned(),
+            "trace: sum: 3.0".to_owned(),
+            r#"trace: xs: [1.0,"<unevaluated>",3.0]"#.to_owned(),
+        ]
+    );
+
+    // Tracing neither forces nor fails on elements which the program does not use.
+    messages.borrow_mut().clear();
+    let options = Options {
+        diagnostics: Diagnostics::Capture(Rc::clone(&messages)),
+        ..Default::default()
+    };
+    let source = r#"
+        local f(n) = [n, f(n + 1)];
+        [trace("t", [1, 1 + "a"])[0], trace("f", f(0))[0]]
+    "#;
+    let value = eval_source_with_options(options, source).unwrap();
+    assert_eq!(serde_json::to_string(&value).unwrap(), "[1.0,0.0]");
+    assert_eq!(
+        *messages.borrow(),
+        vec![
+            r#"trace: t: ["<unevaluated>","<unevaluated>"]"#.to_owned(),
+            r#"trace: f: ["<unevaluated>","<unevaluated>"]"#.to_owned(),
+        ]
+    );
+
+    assert_eq!(
+        crate::eval::eval_to_json("trace(1, 2)"),
+        Err("bad operand type: expected=String, actual=Number".to_owned())
+    );
+}
+
+#[test]
+fn diff_test() {
+    use crate::eval::eval_to_json;
+
+    let a = r#"{name: "app", port: 80, tags: ["a"], db: {host: "x", user: "root"}}"#;
+    let b = r#"{name: "app", port: 8080, tags: ["a"], db: {host: "y", pass: "p"}, new: true}"#;
+    assert_eq!(
+        eval_to_json(&format!("diff({a}, {b})")),
+        Ok(concat!(
+            r#"{"added":{"new":true},"#,
+            r#""changed":{"#,
+            r#""db":{"added":{"pass":"p"},"changed":{"host":{"from":"x","op":"replaced","to":"y"}},"#,
+            r#""op":"modified","removed":{"user":"root"}},"#,
+            r#""port":{"from":80.0,"op":"replaced","to":8080.0}},"#,
+            r#""op":"modified","removed":{}}"#
+        )
+        .to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(&format!("diff({a}, {a})")),
+        Ok(r#"{"op":"unchanged"}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("diff([1, 2], [1, 3])"),
+        Ok(r#"{"from":[1.0,2.0],"op":"replaced","to":[1.0,3.0]}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("diff({a: 1}, [1])"),
+        Ok(r#"{"from":{"a":1.0},"op":"replaced","to":[1.0]}"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("diff({f: function(x) x}, {f: 1})"),
+        Err("cannot compare".to_owned())
+    );
+}
+
+#[test]
+fn merge_all_test() {
+    use crate::eval::eval_to_json;
+
+    let base = r#"{name: "app", server: {host: "localhost", port: 80}, debug: false}"#;
+    let override1 = r#"{server: {port: 8080}}"#;
+    let override2 = r#"{server: {port: 9090, tls: true}, debug: true}"#;
+    assert_eq!(
+        eval_to_json(&format!("mergeAll([{base}, {override1}, {override2}])")),
+        Ok(concat!(
+            r#"{"debug":true,"name":"app","#,
+            r#""server":{"host":"localhost","port":9090.0,"tls":true}}"#
+        )
+        .to_owned())
+    );
+
+    // A non-dict value replaces a dict and vice versa.
+    assert_eq!(
+        eval_to_json(r#"mergeAll([{a: {b: 1}}, {a: 2}, {c: 3}, {c: {d: 4}}])"#),
+        Ok(r#"{"a":2.0,"c":{"d":4.0}}"#.to_owned())
+    );
+    assert_eq!(eval_to_json("mergeAll([])"), Ok("{}".to_owned()));
+    // Values which are not overridden are not forced.
+    assert_eq!(
+        eval_to_json(r#"keys(mergeAll([{a: 1 + "x"}, {b: 2}]))"#),
+        Ok(r#"["a","b"]"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("mergeAll([{a: 1}, [2]])"),
+        Err("bad operand type: expected=Dict, actual=Array".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("mergeAll({a: 1})"),
+        Err("bad operand type: expected=Array, actual=Dict".to_owned())
+    );
+}
+
+#[test]
+fn type_predicates_test() {
+    use crate::eval::eval_to_json;
+
+    let verify = |predicate: &str, matching: &str, non_matching: &str| {
+        assert_eq!(
+            eval_to_json(&format!(
+                "[{predicate}({matching}), {predicate}({non_matching})]"
+            )),
+            Ok("[true,false]".to_owned()),
+            "{predicate}"
+        );
+    };
+    verify("isNull", "null", "false");
+    verify("isBool", "false", "null");
+    verify("isNumber", "0", "\"0\"");
+    verify("isString", "\"\"", "[]");
+    verify("isArray", "[1]", "{}");
+    verify("isDict", "{a: []}", "[{}]");
+    verify("isFunction", "function(x) x", "{}");
+    verify("isFunction", "sum", "1");
+
+    assert_eq!(
+        eval_to_json("isNull()"),
+        Err("wrong number of arguments: expected 1, got 0".to_owned())
+    );
+}
+
+#[test]
+fn assert_equal_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("assertEq(sum([1, 2]), 3) + 1"),
+        Ok("4.0".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"assertEq({a: [1, "x"]}, {a: [1, "x"]})"#),
+        Ok(r#"{"a":[1.0,"x"]}"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(r#"assertEq({a: [1, 1 + 1]}, {a: [1, "2"]})"#),
+        Err(r#"assertion failed: expected {"a":[1.0,"2"]}, got {"a":[1.0,2.0]}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("assertEq(function(x) x, 1)"),
+        Err("cannot compare".to_owned())
+    );
+}
+
+#[test]
+fn memoize_test() {
+    use std::time::{Duration, Instant};
+
+    use crate::eval::eval_to_json;
+
+    let start = Instant::now();
+    assert_eq!(
+        eval_to_json(
+            "local fib = memoize(function(n) if n == 0 || n == 1 then n else fib(n - 1) + fib(n - 2)); fib(30)"
+        ),
+        Ok("832040.0".to_owned())
+    );
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // Calls with multiple arguments are cached by all of them.
+    assert_eq!(
+        eval_to_json(
+            "local add = memoize(function(x, y) {x: x, y: y}); [add(1, [2]), add(1, [3]), add(1, [2]).y]"
+        ),
+        Ok(r#"[{"x":1.0,"y":[2.0]},{"x":1.0,"y":[3.0]},[2.0]]"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json("local f = memoize(function(x) random()); f(1) == f(1)"),
+        Ok("true".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("isFunction(memoize(sum))"),
+        Ok("true".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("memoize(function(f) f(1))(function(x) x)"),
+        Err(
+            "invalid argument: a memoized function cannot take a function as an argument"
+                .to_owned()
+        )
+    );
+    assert_eq!(
+        eval_to_json("memoize(function(x) x)(1 + \"a\")"),
+        Err("bad operand type: expected=(Number + Number) or (String + String), actual=Number + String".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("memoize(1)"),
+        Err("bad operand type: expected=Function, actual=Number".to_owned())
+    );
+}
+
+#[test]
+fn with_default_test() {
+    use crate::eval::eval_to_json;
+
+    let source = |body: &str| format!(r#"local d = withDefault({{a: 1, b: null}}, "n/a"); {body}"#);
+    assert_eq!(
+        eval_to_json(&source(r#"[d.a, d.b, d.c, d["a"], d["c"]]"#)),
+        Ok(r#"[1.0,null,"n/a",1.0,"n/a"]"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&source("[d, keys(d)]")),
+        Ok(r#"[{"a":1.0,"b":null},["a","b"]]"#.to_owned())
+    );
+    // The default is not evaluated unless a missing field is accessed.
+    assert_eq!(
+        eval_to_json(r#"withDefault({a: 1}, 1 + "x").a"#),
+        Ok("1.0".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"withDefault({a: 1}, 1 + "x").b"#),
+        Err("bad operand type: expected=(Number + Number) or (String + String), actual=Number + String".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("{a: 1}.b"),
+        Err("field does not exit: b".to_owned())
+    );
+    // The default is dropped by builtins building a new dict.
+    for body in [
+        r#"pick(["a"], d).c"#,
+        r#"omit(["a"], d).c"#,
+        "mapValues(function(v) v, d).c",
+    ] {
+        assert_eq!(
+            eval_to_json(&source(body)),
+            Err("field does not exit: c".to_owned())
+        );
+    }
+    assert_eq!(
+        eval_to_json(&source(r#"omit(["a"], d)"#)),
+        Ok(r#"{"b":null}"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json("withDefault([1], 0)"),
+        Err("bad operand type: expected=Dict, actual=Array".to_owned())
+    );
+}
+
+#[test]
+fn round_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json(
+            "[round(3.14159, 2), round(3.14159, 0), round(3.14159), round(2.5), round(-2.5)]"
+        ),
+        Ok("[3.14,3.0,3.0,3.0,-3.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("[floor(3.14159, 3), floor(-3.14159, 1), floor(2.7)]"),
+        Ok("[3.141,-3.2,2.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("[ceil(3.14159, 3), ceil(-3.14159, 1), ceil(2.1)]"),
+        Ok("[3.142,-3.1,3.0]".to_owned())
+    );
+    // Negative places round to tens, hundreds and so on.
+    assert_eq!(
+        eval_to_json("[round(1250, -2), floor(1299, -2), ceil(1201, -1)]"),
+        Ok("[1300.0,1200.0,1210.0]".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(r#"round("1")"#),
+        Err("bad operand type: expected=Number, actual=String".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("floor(1, 0.5)"),
+        Err("invalid argument: 0.5 is not an integer".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("ceil()"),
+        Err("wrong number of arguments: expected 1, got 0".to_owned())
+    );
+
+    // Extreme places and numbers too large to scale do not turn into null.
+    assert_eq!(
+        eval_to_json("[round(1, 400), round(1.5, 308), round(5, -400), floor(5, -400)]"),
+        Ok("[1.0,1.5,0.0,0.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("[round(1e300, 10), floor(-1e300, 10), ceil(1e308, 1)]"),
+        Ok("[1e300,-1e300,1e308]".to_owned())
+    );
+}
+
+#[test]
+fn get_path_test() {
+    use crate::eval::eval_to_json;
+
+    let data = r#"{a: [{b: "x"}, {b: "yz", c: null}], d: 1}"#;
+    assert_eq!(
+        eval_to_json(&format!(r#"getPath({data}, ["a", 1, "b"])"#)),
+        Ok(r#""yz""#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!(r#"getPath({data}, ["a", 1, "b", 1])"#)),
+        Ok(r#""z""#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!(
+            r#"[getPath({data}, []), getPath({data}, ["a", 1, "c"])]"#
+        )),
+        Ok(r#"[{"a":[{"b":"x"},{"b":"yz","c":null}],"d":1.0},null]"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(&format!(r#"getPath({data}, ["a", 0, "c"])"#)),
+        Err("path a[0].c: field does not exist at c".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!(r#"getPath({data}, ["a", 2, "b"])"#)),
+        Err("path a[2].b: index out of bounds at [2]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!(r#"getPath({data}, ["d", "e"])"#)),
+        Err("path d.e: cannot access Number at e".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!(r#"getPath({data}, ["a", true])"#)),
+        Err("bad operand type: expected=String or Number, actual=Bool".to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(&format!(r#"getPathOr({data}, ["a", 0, "b"], "none")"#)),
+        Ok(r#""x""#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&format!(
+            r#"[getPathOr({data}, ["a", 0, "c"], "none"), getPathOr({data}, ["a", -1], 0)]"#
+        )),
+        Ok(r#"["none",0.0]"#.to_owned())
+    );
+    // Errors other than a miss are not hidden.
+    assert_eq!(
+        eval_to_json(r#"getPathOr({a: 1 + "x"}, ["a"], 0)"#),
+        Err("bad operand type: expected=(Number + Number) or (String + String), actual=Number + String".to_owned())
+    );
+}
+
+#[test]
+fn sort_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json("sort([3, 1, 2, 1])"),
+        Ok("[1.0,1.0,2.0,3.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"sort(["b", "a", "B"])"#),
+        Ok(r#"["B","a","b"]"#.to_owned())
+    );
+    assert_eq!(eval_to_json("sort([])"), Ok("[]".to_owned()));
+
+    let people = r#"[{n: "a", age: 30}, {n: "b", age: 20}, {n: "c", age: 30}, {n: "d", age: 20}]"#;
+    let names = |sort: &str| {
+        format!("local xs = {sort}(function(p) p.age, {people}); tabulate(4, function(i) xs[i].n)")
+    };
+    // Elements with equal keys keep their input order in both directions.
+    assert_eq!(
+        eval_to_json(&names("sortBy")),
+        Ok(r#"["b","d","a","c"]"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&names("sortByDesc")),
+        Ok(r#"["a","c","b","d"]"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(r#"sort([1, "a"])"#),
+        Err("cannot compare".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("sortBy(function(x) x, [[1], [2]])"),
+        Err("cannot compare".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("sortByDesc(function(x) x, [1, 0 / 0])"),
+        Err("cannot compare".to_owned())
+    );
+
+    // Long enough for the sort algorithm to rely on the comparator being a total order.
+    let many = |extra: &str| {
+        let numbers: Vec<_> = (0..30).map(|i| ((i * 7) % 30).to_string()).collect();
+        format!("[{}, {extra}, 5, 2]", numbers.join(", "))
+    };
+    for extra in ["0 / 0", r#""a""#, "null", "[1]"] {
+        assert_eq!(
+            eval_to_json(&format!("sort({})", many(extra))),
+            Err("cannot compare".to_owned())
+        );
+        assert_eq!(
+            eval_to_json(&format!("sortByDesc(function(x) x, {})", many(extra))),
+            Err("cannot compare".to_owned())
+        );
+    }
+    assert_eq!(
+        eval_to_json(&format!("sort({})[0]", many("-1"))),
+        Ok("-1.0".to_owned())
+    );
+}
+
+#[test]
+fn invert_test() {
+    use crate::eval::eval_to_json;
+
+    assert_eq!(
+        eval_to_json(r#"invert({"a": "x", "b": "y"})"#),
+        Ok(r#"{"x":"a","y":"b"}"#.to_owned())
+    );
+    assert_eq!(eval_to_json("invert({})"), Ok("{}".to_owned()));
+
+    // `a` and `c` are both mapped to `x`; `c` is inserted later, so it wins.
+    assert_eq!(
+        eval_to_json(r#"invert({a: "x", b: "y", c: "x"})"#),
+        Ok(r#"{"x":"c","y":"b"}"#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"keys(invert({b: "2", a: "1"}))"#),
+        Ok(r#"["2","1"]"#.to_owned())
+    );
+
+    assert_eq!(
+        eval_to_json(r#"invert({a: 1})"#),
+        Err("bad operand type: expected=String, actual=Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(r#"invert(["a"])"#),
+        Err("bad operand type: expected=Dict, actual=Array".to_owned())
+    );
+}
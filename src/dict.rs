@@ -0,0 +1,74 @@
+use std::rc::Rc;
+
+use compact_str::CompactString;
+
+use crate::value::Thunk;
+
+// A persistent map from keys to thunks which remembers the insertion order of the keys.
+// Overwriting an existing key replaces the value but keeps the original position of the key.
+#[derive(Debug, Clone, Default)]
+pub struct Dict {
+    entries: im_rc::HashMap<CompactString, (usize, Rc<Thunk>)>,
+    order: im_rc::OrdMap<usize, CompactString>,
+    next_seq: usize,
+    // The value of missing fields given by `withDefault`. It is not an entry of the dict,
+    // so it is neither iterated nor serialized.
+    fallback: Option<Rc<Thunk>>,
+}
+
+impl Dict {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Rc<Thunk>> {
+        self.entries.get(key).map(|(_, thunk)| thunk)
+    }
+
+    pub fn insert(&mut self, key: CompactString, thunk: Rc<Thunk>) {
+        let seq = match self.entries.get(&key) {
+            Some((seq, _)) => *seq,
+            None => {
+                let seq = self.next_seq;
+                self.next_seq += 1;
+                self.order.insert(seq, key.clone());
+                seq
+            }
+        };
+        self.entries.insert(key, (seq, thunk));
+    }
+
+    pub fn fallback(&self) -> Option<&Rc<Thunk>> {
+        self.fallback.as_ref()
+    }
+
+    pub fn set_fallback(&mut self, thunk: Rc<Thunk>) {
+        self.fallback = Some(thunk);
+    }
+
+    // Iterates over the entries in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&CompactString, &Rc<Thunk>)> {
+        self.order.values().map(|key| (key, &self.entries[key].1))
+    }
+
+    // Returns the entries sorted by key.
+    pub fn sorted(&self) -> Vec<(&CompactString, &Rc<Thunk>)> {
+        let mut items: Vec<_> = self.entries.iter().map(|(k, (_, v))| (k, v)).collect();
+        items.sort_unstable_by_key(|(k, _)| *k);
+        items
+    }
+}
+
+impl FromIterator<(CompactString, Rc<Thunk>)> for Dict {
+    fn from_iter<I: IntoIterator<Item = (CompactString, Rc<Thunk>)>>(iter: I) -> Self {
+        let mut dict = Dict::new();
+        for (key, thunk) in iter {
+            dict.insert(key, thunk);
+        }
+        dict
+    }
+}
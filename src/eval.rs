@@ -1,12 +1,19 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell, RefMut};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use compact_str::{CompactString, ToCompactString};
 
-use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::ast::{Arg, BinaryOp, Expr, Params, Span, UnaryOp};
+use crate::builtins::BUILTINS;
+use crate::dict::Dict;
+use crate::random::Rng;
 use crate::symbol::Symbol;
 use crate::types::Erasure;
-use crate::value::{Thunk, Value};
+use crate::value::{KeyOrder, Memoized, Thunk, Value};
 
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum EvalError {
@@ -25,37 +32,294 @@ pub enum EvalError {
     #[error("index out of bounds: {0}")]
     IndexOutOfBounds(usize),
 
+    #[error("no arm of switch-expression matched")]
+    NoMatch,
+
     #[error("cannot compare")]
     CannotCompare,
 
-    #[error("not callable")]
-    NotCallable,
+    #[error("not callable: {0}")]
+    NotCallable(Erasure),
+
+    #[error("wrong number of arguments: expected {expected}, got {actual}")]
+    WrongNumberOfArguments { expected: usize, actual: usize },
+
+    #[error("unknown argument: {0}")]
+    UnknownArgument(Symbol),
+
+    #[error("duplicate argument: {0}")]
+    DuplicateArgument(Symbol),
+
+    #[error("positional argument must not follow named arguments")]
+    PositionalAfterNamed,
+
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error("path {path}: {reason} at {segment}")]
+    PathNotFound {
+        path: String,
+        segment: String,
+        reason: String,
+    },
+
+    #[error("assertion failed: expected {expected}, got {actual}")]
+    AssertionFailed { expected: String, actual: String },
+
+    #[error("cannot find {name:?} to import (tried: {})", display_paths(tried))]
+    ImportNotFound { name: String, tried: Vec<PathBuf> },
+
+    #[error("cannot import {path:?}: {message}")]
+    ImportFailed { path: PathBuf, message: String },
+
+    #[error("import cycle: {}", display_import_cycle(cycle))]
+    ImportCycle { cycle: Vec<PathBuf> },
+
+    #[error("{error}")]
+    At {
+        span: Span,
+        // The source which `span` points into. `None` means the main source code.
+        origin: Option<Arc<Source>>,
+        error: Box<EvalError>,
+    },
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+    paths.join(", ")
+}
 
-    #[error("wrong number of arguments")]
-    WrongNumberOfArguments,
+fn display_import_cycle(cycle: &[PathBuf]) -> String {
+    let paths: Vec<_> = cycle.iter().map(|p| p.display().to_string()).collect();
+    paths.join(" -> ")
+}
+
+impl EvalError {
+    // Attaches `span` in `source` to the error unless it already has one.
+    // Errors are propagated from inner expressions to outer ones, so the innermost span wins.
+    fn at(self, span: Span, source: Option<&Arc<Source>>) -> EvalError {
+        match self {
+            EvalError::At { .. } => self,
+            error => EvalError::At {
+                span,
+                origin: source.cloned(),
+                error: Box::new(error),
+            },
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            EvalError::At { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    // Returns the source which `span()` points into, if it is not the main source code.
+    pub fn origin(&self) -> Option<&Source> {
+        match self {
+            EvalError::At { origin, .. } => origin.as_deref(),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn without_span(self) -> EvalError {
+        match self {
+            EvalError::At { error, .. } => *error,
+            error => error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // Seed for the PRNG used by `random` and `randomInt`.
+    // If it is not given, the PRNG is seeded from the current time.
+    pub seed: Option<u64>,
+
+    // Directories searched by `import` after the directory of the importing file.
+    pub include_paths: Vec<PathBuf>,
+
+    // If true, undefined variables evaluate to null with a warning instead of an error.
+    pub lenient: bool,
+
+    // If true, statistics of the evaluation are collected into `Runtime::profile`.
+    pub profile: bool,
+
+    // If true, imported files are lexed with `Lexer::strict_numbers`.
+    pub strict_numbers: bool,
+
+    // The order of keys in the output, which `entries` follows as well.
+    pub key_order: KeyOrder,
+
+    pub diagnostics: Diagnostics,
+}
+
+// The destination of warnings and other messages reported during evaluation.
+#[derive(Debug, Clone, Default)]
+pub enum Diagnostics {
+    #[default]
+    Stderr,
+    // Keeps the messages in memory so that they can be inspected later.
+    #[cfg_attr(not(test), allow(dead_code))]
+    Capture(Rc<RefCell<Vec<String>>>),
+}
+
+impl Diagnostics {
+    fn emit(&self, message: String) {
+        match self {
+            Diagnostics::Stderr => eprintln!("{message}"),
+            Diagnostics::Capture(messages) => messages.borrow_mut().push(message),
+        }
+    }
+}
+
+// The state shared by every `Env` derived from the same root.
+#[derive(Debug)]
+pub struct Runtime {
+    rng: RefCell<Rng>,
+    include_paths: Vec<PathBuf>,
+    lenient: bool,
+    strict_numbers: bool,
+    key_order: KeyOrder,
+    diagnostics: Diagnostics,
+    profile: Option<Profile>,
+}
+
+// Statistics of the evaluation collected with `--profile`.
+#[derive(Debug, Default)]
+pub struct Profile {
+    forced_thunks: Cell<usize>,
+}
+
+impl Profile {
+    // Counts a thunk evaluated for the first time. Forcing an evaluated thunk again is not counted.
+    pub fn count_force(&self) {
+        self.forced_thunks.set(self.forced_thunks.get() + 1);
+    }
+
+    pub fn forced_thunks(&self) -> usize {
+        self.forced_thunks.get()
+    }
+}
+
+impl Runtime {
+    fn new(options: Options) -> Self {
+        let seed = options.seed.unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            now.as_nanos() as u64
+        });
+        Self {
+            rng: RefCell::new(Rng::new(seed)),
+            include_paths: options.include_paths,
+            lenient: options.lenient,
+            strict_numbers: options.strict_numbers,
+            key_order: options.key_order,
+            diagnostics: options.diagnostics,
+            profile: options.profile.then(Profile::default),
+        }
+    }
+
+    pub fn rng(&self) -> RefMut<'_, Rng> {
+        self.rng.borrow_mut()
+    }
+
+    pub fn warn(&self, message: &str) {
+        self.diagnostics.emit(format!("warning: {message}"));
+    }
+
+    pub fn trace(&self, message: &str) {
+        self.diagnostics.emit(format!("trace: {message}"));
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn key_order(&self) -> &KeyOrder {
+        &self.key_order
+    }
+}
+
+// Source code other than the main one, such as an imported file.
+// Spans of errors raised in it are reported against it.
+// It is shared by `Arc` rather than `Rc` because `EvalError` has to be `Send` for anyhow.
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    pub code: String,
 }
 
 #[derive(Clone, Debug)]
 pub struct Env {
     variables: im_rc::HashMap<Symbol, Rc<Thunk>>,
+    runtime: Rc<Runtime>,
+    // The directory of the source file, against which `import` resolves relative paths.
+    // `None` means the current working directory.
+    current_dir: Option<Rc<Path>>,
+    // The source code of the expressions evaluated in this environment.
+    // `None` means the main source code.
+    source: Option<Arc<Source>>,
+    // The canonical paths of the imported files through which this environment is reached,
+    // outermost first. Thunks keep it, so a cycle is found even if it goes through lazy values.
+    imports: im_rc::Vector<Rc<Path>>,
 }
 
 impl Env {
-    pub fn new() -> Self {
+    // Creates the root environment, in which the builtin functions are defined.
+    pub fn new(options: Options) -> Self {
+        Self::root(Rc::new(Runtime::new(options)), None, None)
+    }
+
+    fn root(
+        runtime: Rc<Runtime>,
+        current_dir: Option<Rc<Path>>,
+        source: Option<Arc<Source>>,
+    ) -> Self {
+        let mut variables = im_rc::HashMap::new();
+        for builtin in BUILTINS {
+            let thunk = Thunk::from_value(Value::Builtin(*builtin));
+            variables.insert(builtin.name.into(), Rc::new(thunk));
+        }
         Self {
-            variables: im_rc::HashMap::new(),
+            variables,
+            runtime,
+            current_dir,
+            source,
+            imports: im_rc::Vector::new(),
         }
     }
 
     pub fn with_variable(&self, name: Symbol, thunk: Rc<Thunk>) -> Env {
         Self {
             variables: self.variables.update(name, thunk),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_current_dir(&self, dir: &Path) -> Env {
+        Self {
+            current_dir: Some(Rc::from(dir)),
+            ..self.clone()
+        }
+    }
+
+    // Returns the environment to evaluate expressions parsed from `source`.
+    pub fn with_source(&self, source: Source) -> Env {
+        Self {
+            source: Some(Arc::new(source)),
+            ..self.clone()
         }
     }
 
     pub fn lookup(&self, name: &Symbol) -> Option<Rc<Thunk>> {
         self.variables.get(name).cloned()
     }
+
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
 }
 
 pub type Result<T> = std::result::Result<T, EvalError>;
@@ -68,15 +332,20 @@ pub fn eval_expr(env: &Env, expr: &Expr) -> Result<Value> {
         Expr::String(s) => Ok(Value::String(Rc::clone(s))),
         Expr::Array(array) => eval_array(env, array),
         Expr::Dict(key_values) => eval_dict(env, key_values),
-        Expr::Function(args, expr) => eval_function_literal(env, args, expr),
+        Expr::Function(params, expr) => eval_function_literal(env, params, expr),
         Expr::Variable(name) => eval_variable(env, name),
         Expr::UnaryOp(op, expr) => eval_unary_op(env, *op, expr),
         Expr::BinaryOp(op, lhs, rhs) => eval_binary_op(env, *op, lhs, rhs),
         Expr::If(cond, then, else_) => eval_if(env, cond, then, else_),
         Expr::Local(name, expr1, expr2) => eval_local(env, name, expr1, expr2),
+        Expr::Switch(expr, arms) => eval_switch(env, expr, arms),
         Expr::FunctionCall(func, args) => eval_function_call(env, func, args),
         Expr::FieldAccess(expr, name) => eval_field_access(env, expr, name),
         Expr::IndexAccess(expr, index) => eval_index_access(env, expr, index),
+        Expr::Import(name) => eval_import(env, name),
+        Expr::Located(span, expr) => {
+            eval_expr(env, expr).map_err(|e| e.at(*span, env.source.as_ref()))
+        }
     }
 }
 
@@ -90,18 +359,18 @@ fn eval_array(env: &Env, array: &[Expr]) -> Result<Value> {
 }
 
 fn eval_dict(env: &Env, key_values: &[(CompactString, Expr)]) -> Result<Value> {
-    let mut dict = HashMap::new();
+    let mut dict = Dict::new();
     for (key, expr) in key_values {
         let thunk = Thunk::new(env.clone(), Box::new(expr.clone()));
         dict.insert(key.clone(), Rc::new(thunk));
     }
-    Ok(Value::Dict(dict.into()))
+    Ok(Value::Dict(dict))
 }
 
-fn eval_function_literal(env: &Env, args: &[Symbol], expr: &Expr) -> Result<Value> {
+fn eval_function_literal(env: &Env, params: &Params, expr: &Expr) -> Result<Value> {
     Ok(Value::Closure(
         env.clone(),
-        args.to_vec(),
+        params.clone(),
         Rc::new(expr.clone()),
     ))
 }
@@ -109,6 +378,11 @@ fn eval_function_literal(env: &Env, args: &[Symbol], expr: &Expr) -> Result<Valu
 fn eval_variable(env: &Env, name: &Symbol) -> Result<Value> {
     match env.lookup(name) {
         Some(value) => Ok(value.force()?),
+        None if env.runtime.lenient => {
+            env.runtime
+                .warn(&format!("undefined variable {name:?} is evaluated as null"));
+            Ok(Value::Null)
+        }
         None => Err(EvalError::UndefinedVariable(name.clone())),
     }
 }
@@ -289,27 +563,233 @@ fn eval_local(env: &Env, name: &Symbol, expr1: &Expr, expr2: &Expr) -> Result<Va
     eval_expr(&new_env, expr2)
 }
 
-fn eval_function_call(env: &Env, func: &Expr, args: &[Expr]) -> Result<Value> {
+fn eval_switch(env: &Env, expr: &Expr, arms: &[(Option<Expr>, Expr)]) -> Result<Value> {
+    let value = eval_expr(env, expr)?;
+    for (pattern, body) in arms {
+        let matched = match pattern {
+            Some(pattern) => Value::try_eq(&value, &eval_expr(env, pattern)?)?,
+            None => true,
+        };
+        if matched {
+            return eval_expr(env, body);
+        }
+    }
+    Err(EvalError::NoMatch)
+}
+
+fn eval_function_call(env: &Env, func: &Expr, args: &[Arg]) -> Result<Value> {
     let func_value = eval_expr(env, func)?;
-    match func_value {
-        Value::Closure(closure_env, params, expr) => {
-            if args.len() != params.len() {
-                return Err(EvalError::WrongNumberOfArguments);
+    let mut positional = Vec::new();
+    let mut named = Vec::new();
+    for arg in args {
+        match arg {
+            Arg::Positional(arg) => {
+                if !named.is_empty() {
+                    return Err(EvalError::PositionalAfterNamed);
+                }
+                let thunk = Thunk::new(env.clone(), Box::new(arg.clone()));
+                positional.push(Rc::new(thunk));
             }
-            let mut new_env = closure_env;
-            for (param, arg) in params.iter().zip(args) {
+            Arg::Named(name, arg) => {
                 let thunk = Thunk::new(env.clone(), Box::new(arg.clone()));
-                new_env = new_env.with_variable(param.clone(), Rc::new(thunk));
+                named.push((name.clone(), Rc::new(thunk)));
+            }
+            Arg::Spread(arg) => {
+                if !named.is_empty() {
+                    return Err(EvalError::PositionalAfterNamed);
+                }
+                match eval_expr(env, arg)? {
+                    Value::Array(array) => positional.extend(array),
+                    value => {
+                        return Err(EvalError::BadOperandType {
+                            expected: Erasure::Array.to_string(),
+                            actual: value.erasure().to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    apply(env, func_value, positional, named)
+}
+
+// Calls `func` with positional arguments only.
+// This is the entry point for builtin functions that take a function as an argument.
+pub fn call_function(env: &Env, func: &Value, args: Vec<Rc<Thunk>>) -> Result<Value> {
+    apply(env, func.clone(), args, Vec::new())
+}
+
+fn apply(
+    env: &Env,
+    func: Value,
+    positional: Vec<Rc<Thunk>>,
+    named: Vec<(Symbol, Rc<Thunk>)>,
+) -> Result<Value> {
+    match func {
+        Value::Closure(closure_env, params, expr) => {
+            match bind_arguments(closure_env, &params, positional, named)? {
+                (new_env, None) => eval_expr(&new_env, &expr),
+                (new_env, Some(remaining)) => Ok(Value::Closure(new_env, remaining, expr)),
+            }
+        }
+        Value::Builtin(builtin) => {
+            // Builtin functions have no parameter names to match against.
+            if let Some((name, _)) = named.into_iter().next() {
+                return Err(EvalError::UnknownArgument(name));
+            }
+            (builtin.func)(env, &positional)
+        }
+        Value::Memoized(memoized) => {
+            // The cache is keyed by position, so naming arguments is not supported.
+            if let Some((name, _)) = named.into_iter().next() {
+                return Err(EvalError::UnknownArgument(name));
+            }
+            apply_memoized(env, &memoized, positional)
+        }
+        value => Err(EvalError::NotCallable(value.erasure())),
+    }
+}
+
+// Calls the memoized function, or returns the cached result if it has been called with
+// arguments equal to `positional`. All the arguments are forced to look up the cache.
+fn apply_memoized(env: &Env, memoized: &Memoized, positional: Vec<Rc<Thunk>>) -> Result<Value> {
+    let mut key = Vec::with_capacity(positional.len());
+    for thunk in &positional {
+        match thunk.force()?.to_key() {
+            Ok(k) => key.push(k),
+            Err(EvalError::CannotCompare) => {
+                return Err(EvalError::InvalidArgument(
+                    "a memoized function cannot take a function as an argument".to_owned(),
+                ));
             }
-            eval_expr(&new_env, &expr)
+            Err(e) => return Err(e),
         }
-        _ => Err(EvalError::NotCallable),
     }
+    if let Some(value) = memoized.get(&key) {
+        return Ok(value);
+    }
+    let value = call_function(env, &memoized.func, positional)?;
+    memoized.insert(key, value.clone());
+    Ok(value)
+}
+
+// Binds arguments to `params` and returns the extended `env`.
+// Positional arguments come first and fill the fixed parameters from left to right;
+// the surplus goes to the rest parameter. Named arguments then fill the remaining
+// fixed parameters by name. Naming a parameter that is already filled is an error.
+//
+// If some fixed parameters are left unfilled, the function is partially applied:
+// the unfilled parameters are returned so that the caller can build a closure
+// awaiting the rest of the arguments.
+fn bind_arguments(
+    mut env: Env,
+    params: &Params,
+    positional: Vec<Rc<Thunk>>,
+    named: Vec<(Symbol, Rc<Thunk>)>,
+) -> Result<(Env, Option<Params>)> {
+    let num_args = positional.len() + named.len();
+    let mut bound = vec![None; params.fixed.len()];
+    let mut positional = positional.into_iter();
+    for (slot, thunk) in bound.iter_mut().zip(positional.by_ref()) {
+        *slot = Some(thunk);
+    }
+    let surplus: im_rc::Vector<_> = positional.collect();
+    if !surplus.is_empty() && params.rest.is_none() {
+        return Err(EvalError::WrongNumberOfArguments {
+            expected: params.fixed.len(),
+            actual: num_args,
+        });
+    }
+
+    for (name, thunk) in named {
+        let Some(i) = params.fixed.iter().position(|param| *param == name) else {
+            return Err(EvalError::UnknownArgument(name));
+        };
+        if bound[i].is_some() {
+            return Err(EvalError::DuplicateArgument(name));
+        }
+        bound[i] = Some(thunk);
+    }
+
+    let mut unbound = Vec::new();
+    for (param, thunk) in params.fixed.iter().zip(bound) {
+        match thunk {
+            Some(thunk) => env = env.with_variable(param.clone(), thunk),
+            None => unbound.push(param.clone()),
+        }
+    }
+    if !unbound.is_empty() {
+        let remaining = Params {
+            fixed: unbound,
+            rest: params.rest.clone(),
+        };
+        return Ok((env, Some(remaining)));
+    }
+    if let Some(rest) = &params.rest {
+        let thunk = Thunk::from_value(Value::Array(surplus));
+        env = env.with_variable(rest.clone(), Rc::new(thunk));
+    }
+    Ok((env, None))
+}
+
+// Evaluates the file `name` in a fresh root environment.
+// `name` is looked up in the directory of the importing file and then in the include paths.
+fn eval_import(env: &Env, name: &str) -> Result<Value> {
+    let current_dir = env.current_dir.as_deref().unwrap_or(Path::new("."));
+    let mut tried = Vec::new();
+    let candidates =
+        std::iter::once(current_dir).chain(env.runtime.include_paths.iter().map(PathBuf::as_path));
+    for dir in candidates {
+        let path = dir.join(name);
+        if path.is_file() {
+            return eval_file(env, &path);
+        }
+        tried.push(path);
+    }
+    Err(EvalError::ImportNotFound {
+        name: name.to_owned(),
+        tried,
+    })
+}
+
+fn eval_file(env: &Env, path: &Path) -> Result<Value> {
+    let failed = |message: String| EvalError::ImportFailed {
+        path: path.to_owned(),
+        message,
+    };
+    let source_code = fs::read_to_string(path).map_err(|e| failed(e.to_string()))?;
+    let lexer = crate::lexer::Lexer::new(&source_code).strict_numbers(env.runtime.strict_numbers);
+    let expr = crate::syntax::ExprParser::new()
+        .parse(lexer)
+        .map_err(|e| failed(e.to_string()))?;
+    let dir = path.parent().map(Rc::from);
+    let source = Source {
+        name: path.display().to_string(),
+        code: source_code,
+    };
+
+    // Importing a file from code which the same file leads to would recurse forever.
+    // This also rejects files referring to each other's fields without a real cycle.
+    let canonical_path: Rc<Path> = fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_owned())
+        .into();
+    if let Some(i) = env.imports.index_of(&canonical_path) {
+        let cycle = env.imports.iter().skip(i).chain([&canonical_path]);
+        let cycle = cycle.map(|p| p.to_path_buf()).collect();
+        return Err(EvalError::ImportCycle { cycle });
+    }
+    let mut imports = env.imports.clone();
+    imports.push_back(canonical_path);
+    let new_env = Env {
+        imports,
+        ..Env::root(Rc::clone(&env.runtime), dir, Some(Arc::new(source)))
+    };
+    eval_expr(&new_env, &expr)
 }
 
 fn eval_field_access(env: &Env, expr: &Expr, name: &Symbol) -> Result<Value> {
     match eval_expr(env, expr)? {
-        Value::Dict(dict) => match dict.get(name) {
+        Value::Dict(dict) => match dict.get(name).or(dict.fallback()) {
             Some(thunk) => Ok(thunk.force()?),
             None => Err(EvalError::FieldDoesNotExist(name.clone())),
         },
@@ -353,7 +833,7 @@ fn eval_index_access(env: &Env, expr: &Expr, index: &Expr) -> Result<Value> {
         Value::Dict(dict) => match index_value {
             Value::String(s) => {
                 let s = s.to_compact_string();
-                match dict.get(&s) {
+                match dict.get(&s).or(dict.fallback()) {
                     Some(thunk) => Ok(thunk.force()?),
                     None => Err(EvalError::FieldDoesNotExist(s)),
                 }
@@ -374,3 +854,398 @@ fn eval_index_access(env: &Env, expr: &Expr, index: &Expr) -> Result<Value> {
         }),
     }
 }
+
+#[cfg(test)]
+pub fn eval_source_with_options(options: Options, source: &str) -> Result<Value> {
+    let lexer = crate::lexer::Lexer::new(source);
+    let expr = crate::syntax::ExprParser::new().parse(lexer).unwrap();
+    eval_expr(&Env::new(options), &expr).map_err(EvalError::without_span)
+}
+
+#[cfg(test)]
+pub fn eval_source(source: &str) -> Result<Value> {
+    eval_source_with_options(Options::default(), source)
+}
+
+#[cfg(test)]
+pub fn eval_to_json(source: &str) -> std::result::Result<String, String> {
+    let value = eval_source(source).map_err(|e| e.to_string())?;
+    serde_json::to_string(&value).map_err(|e| e.to_string())
+}
+
+#[test]
+fn switch_test() {
+    let source = |x: &str| format!(r#"switch {x} {{ 1 => "one", 2 => "two", _ => "many" }}"#);
+    assert_eq!(eval_to_json(&source("1")), Ok(r#""one""#.to_owned()));
+    assert_eq!(eval_to_json(&source("1 + 1")), Ok(r#""two""#.to_owned()));
+    assert_eq!(eval_to_json(&source("3")), Ok(r#""many""#.to_owned()));
+
+    assert!(matches!(
+        eval_source(r#"switch "c" { "a" => 1, "b" => 2 }"#),
+        Err(EvalError::NoMatch)
+    ));
+
+    // Negative numbers can be patterns.
+    let source = |x: &str| {
+        format!(r#"switch {x} {{ -1 => "minus one", -0.5 => "minus half", _ => "other" }}"#)
+    };
+    assert_eq!(
+        eval_to_json(&source("0 - 1")),
+        Ok(r#""minus one""#.to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&source("-0.5")),
+        Ok(r#""minus half""#.to_owned())
+    );
+    assert_eq!(eval_to_json(&source("1")), Ok(r#""other""#.to_owned()));
+
+    // `_` is a wildcard only in switch arms, and other identifiers are not patterns.
+    assert_eq!(eval_to_json("local _ = 1; _ + 1"), Ok("2.0".to_owned()));
+    assert_eq!(
+        eval_to_json("local _ = 1; switch 2 { _ => _ }"),
+        Ok("1.0".to_owned())
+    );
+    let parse =
+        |source: &str| crate::syntax::ExprParser::new().parse(crate::lexer::Lexer::new(source));
+    assert!(parse("switch 1 { x => 2 }").is_err());
+}
+
+#[test]
+fn variadic_function_test() {
+    let source = |args: &str| format!("local f(first, ...rest) = [first, rest]; f({args})");
+    assert_eq!(eval_to_json(&source("1")), Ok("[1.0,[]]".to_owned()));
+    assert_eq!(
+        eval_to_json(&source("1, 2, 3")),
+        Ok("[1.0,[2.0,3.0]]".to_owned())
+    );
+    assert!(matches!(eval_source(&source("")), Ok(Value::Closure(..))));
+}
+
+#[test]
+fn named_arguments_test() {
+    let source = |args: &str| format!("local f(x, y) = [x, y]; f({args})");
+    assert_eq!(
+        eval_to_json(&source("x: 1, y: 2")),
+        Ok("[1.0,2.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&source("y: 2, x: 1")),
+        Ok("[1.0,2.0]".to_owned())
+    );
+    assert_eq!(eval_to_json(&source("1, y: 2")), Ok("[1.0,2.0]".to_owned()));
+
+    assert!(matches!(
+        eval_source(&source("x: 1, z: 2")),
+        Err(EvalError::UnknownArgument(name)) if name == "z"
+    ));
+    assert!(matches!(
+        eval_source(&source("1, x: 2")),
+        Err(EvalError::DuplicateArgument(name)) if name == "x"
+    ));
+    assert!(matches!(
+        eval_source(&source("x: 1, 2")),
+        Err(EvalError::PositionalAfterNamed)
+    ));
+}
+
+#[test]
+fn partial_application_test() {
+    let source = |body: &str| format!("local add(x, y, z) = x * 100 + y * 10 + z; {body}");
+    assert_eq!(
+        eval_to_json(&source("local add1 = add(1); add1(2, 3)")),
+        Ok("123.0".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&source("add(1)(2)(3)")),
+        Ok("123.0".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&source("add()(1, 2, 3)")),
+        Ok("123.0".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&source("add(y: 2)(1)(3)")),
+        Ok("123.0".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("local f(x, ...xs) = [x, xs]; f()(1, 2)"),
+        Ok("[1.0,[2.0]]".to_owned())
+    );
+
+    assert!(matches!(
+        eval_source(&source("add(1, 2, 3, 4)")),
+        Err(EvalError::WrongNumberOfArguments { .. })
+    ));
+    assert!(matches!(
+        eval_source(&source("add(1)(2, 3, 4)")),
+        Err(EvalError::WrongNumberOfArguments { .. })
+    ));
+}
+
+#[test]
+fn spread_arguments_test() {
+    let source =
+        |args: &str| format!("local f(a, b, c) = [a, b, c]; local xs = [1, 2, 3]; f({args})");
+    assert_eq!(
+        eval_to_json(&source("...xs")),
+        Ok("[1.0,2.0,3.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&source("0, ...[5, 6]")),
+        Ok("[0.0,5.0,6.0]".to_owned())
+    );
+    assert_eq!(
+        eval_to_json(&source("...[], 1, ...[2], 3")),
+        Ok("[1.0,2.0,3.0]".to_owned())
+    );
+
+    assert!(matches!(
+        eval_source(&source("0, ...xs")),
+        Err(EvalError::WrongNumberOfArguments { .. })
+    ));
+    assert!(matches!(
+        eval_source(&source("...1")),
+        Err(EvalError::BadOperandType { .. })
+    ));
+}
+
+#[test]
+fn wrong_number_of_arguments_test() {
+    assert_eq!(
+        eval_to_json("local f(a, b, c) = a; f(1, 2, 3, 4)"),
+        Err("wrong number of arguments: expected 3, got 4".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("local f(a, b) = a; f(1, 2, 3, b: 4)"),
+        Err("wrong number of arguments: expected 2, got 4".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("sum()"),
+        Err("wrong number of arguments: expected 1, got 0".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("sum([1], [2])"),
+        Err("wrong number of arguments: expected 1, got 2".to_owned())
+    );
+}
+
+#[test]
+fn not_callable_test() {
+    assert_eq!(
+        eval_to_json("(1)(2)"),
+        Err("not callable: Number".to_owned())
+    );
+    assert_eq!(
+        eval_to_json("{f: \"g\"}.f()"),
+        Err("not callable: String".to_owned())
+    );
+}
+
+#[test]
+fn error_span_test() {
+    let source = r#"{a: 1, b: {c: [true, 2 + "x"]}}"#;
+    let lexer = crate::lexer::Lexer::new(source);
+    let expr = crate::syntax::ExprParser::new().parse(lexer).unwrap();
+    let value = eval_expr(&Env::new(Options::default()), &expr).unwrap();
+    let error = value.force_deep(None).unwrap_err();
+    let span = error.span().unwrap();
+    assert_eq!(&source[span.start..span.end], r#"2 + "x""#);
+    assert!(matches!(
+        error.without_span(),
+        EvalError::BadOperandType { .. }
+    ));
+
+    let source = "local f(x) = x.foo; [f({foo: 1}), f({bar: 2})]";
+    let lexer = crate::lexer::Lexer::new(source);
+    let expr = crate::syntax::ExprParser::new().parse(lexer).unwrap();
+    let value = eval_expr(&Env::new(Options::default()), &expr).unwrap();
+    let span = value.force_deep(None).unwrap_err().span().unwrap();
+    assert_eq!(&source[span.start..span.end], "x.foo");
+
+    // The first failing value in key order is reported.
+    let source = r#"{e: !2, c: !1, a: 1 + "x", b: -null, d: -"s"}"#;
+    let lexer = crate::lexer::Lexer::new(source);
+    let expr = crate::syntax::ExprParser::new().parse(lexer).unwrap();
+    let value = eval_expr(&Env::new(Options::default()), &expr).unwrap();
+    let span = value.force_deep(None).unwrap_err().span().unwrap();
+    assert_eq!(&source[span.start..span.end], r#"1 + "x""#);
+}
+
+#[test]
+fn import_test() {
+    let root = std::env::temp_dir().join(format!("jack-import-test-{}", std::process::id()));
+    let main_dir = root.join("main");
+    let lib_dir = root.join("lib");
+    fs::create_dir_all(&main_dir).unwrap();
+    fs::create_dir_all(&lib_dir).unwrap();
+    fs::write(
+        lib_dir.join("lib.jack"),
+        r#"{greet(name): "Hello " + import "name.jack" + name}"#,
+    )
+    .unwrap();
+    fs::write(lib_dir.join("name.jack"), r#""dear ""#).unwrap();
+    fs::write(main_dir.join("local.jack"), "42").unwrap();
+
+    let run = |source: &str, include_paths: Vec<PathBuf>| {
+        let options = Options {
+            include_paths,
+            ..Default::default()
+        };
+        let env = Env::new(options).with_current_dir(&main_dir);
+        let lexer = crate::lexer::Lexer::new(source);
+        let expr = crate::syntax::ExprParser::new().parse(lexer).unwrap();
+        let value = eval_expr(&env, &expr).map_err(|e| e.to_string())?;
+        serde_json::to_string(&value).map_err(|e| e.to_string())
+    };
+
+    assert_eq!(run(r#"import "local.jack""#, vec![]), Ok("42.0".to_owned()));
+    assert_eq!(
+        run(
+            r#"(import "lib.jack").greet("Alice")"#,
+            vec![lib_dir.clone()]
+        ),
+        Ok(r#""Hello dear Alice""#.to_owned())
+    );
+    assert_eq!(
+        run(r#"import "lib.jack""#, vec![]),
+        Err(format!(
+            "cannot find \"lib.jack\" to import (tried: {})",
+            main_dir.join("lib.jack").display()
+        ))
+    );
+    assert_eq!(
+        run(r#"import "missing.jack""#, vec![lib_dir.clone()]),
+        Err(format!(
+            "cannot find \"missing.jack\" to import (tried: {}, {})",
+            main_dir.join("missing.jack").display(),
+            lib_dir.join("missing.jack").display()
+        ))
+    );
+
+    fs::write(lib_dir.join("big.jack"), "9007199254740993").unwrap();
+    assert_eq!(
+        run(r#"import "big.jack""#, vec![lib_dir.clone()]),
+        Ok("9007199254740992.0".to_owned())
+    );
+    let options = Options {
+        strict_numbers: true,
+        ..Default::default()
+    };
+    let env = Env::new(options).with_current_dir(&lib_dir);
+    let lexer = crate::lexer::Lexer::new(r#"import "big.jack""#);
+    let expr = crate::syntax::ExprParser::new().parse(lexer).unwrap();
+    assert_eq!(
+        eval_expr(&env, &expr).unwrap_err().to_string(),
+        format!(
+            "cannot import {:?}: integer literal cannot be represented exactly: 9007199254740993",
+            lib_dir.join("big.jack")
+        )
+    );
+
+    // Importing a file which is being imported is an error rather than endless recursion.
+    fs::write(lib_dir.join("self.jack"), r#"import "self.jack""#).unwrap();
+    fs::write(lib_dir.join("a.jack"), r#"1 + import "b.jack""#).unwrap();
+    fs::write(lib_dir.join("b.jack"), r#"{b: import "a.jack"}.b"#).unwrap();
+    let canonical_lib_dir = fs::canonicalize(&lib_dir).unwrap();
+    let cycle = |names: &[&str]| {
+        let paths: Vec<_> = names
+            .iter()
+            .map(|name| canonical_lib_dir.join(name).display().to_string())
+            .collect();
+        format!("import cycle: {}", paths.join(" -> "))
+    };
+    assert_eq!(
+        run(r#"import "self.jack""#, vec![lib_dir.clone()]),
+        Err(cycle(&["self.jack", "self.jack"]))
+    );
+    assert_eq!(
+        run(r#"import "a.jack""#, vec![lib_dir.clone()]),
+        Err(cycle(&["a.jack", "b.jack", "a.jack"]))
+    );
+    // A cycle through lazy fields is found as well.
+    fs::write(
+        lib_dir.join("lazy_a.jack"),
+        r#"{x: (import "lazy_b.jack").y}"#,
+    )
+    .unwrap();
+    fs::write(
+        lib_dir.join("lazy_b.jack"),
+        r#"{y: (import "lazy_a.jack").x}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        run(r#"(import "lazy_a.jack").x"#, vec![lib_dir.clone()]),
+        Err(cycle(&["lazy_a.jack", "lazy_b.jack", "lazy_a.jack"]))
+    );
+    // The same file can be imported more than once if it is not a cycle.
+    assert_eq!(
+        run(r#"[import "local.jack", import "local.jack"]"#, vec![]),
+        Ok("[42.0,42.0]".to_owned())
+    );
+
+    // Errors raised lazily in an imported file point into that file, not into the importer.
+    let padding = " ".repeat(100);
+    fs::write(
+        lib_dir.join("bad.jack"),
+        format!("{{a: 1,{padding}\n  b: 1 + \"x\"}}"),
+    )
+    .unwrap();
+    let env = Env::new(Options::default()).with_current_dir(&lib_dir);
+    let source = r#"(import "bad.jack").b"#;
+    let lexer = crate::lexer::Lexer::new(source);
+    let expr = crate::syntax::ExprParser::new().parse(lexer).unwrap();
+    let error = eval_expr(&env, &expr).unwrap_err();
+    let origin = error.origin().unwrap();
+    let span = error.span().unwrap();
+    assert_eq!(origin.name, lib_dir.join("bad.jack").display().to_string());
+    assert_eq!(&origin.code[span.start..span.end], r#"1 + "x""#);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn lenient_test() {
+    let messages = Rc::new(RefCell::new(Vec::new()));
+    let options = Options {
+        lenient: true,
+        diagnostics: Diagnostics::Capture(Rc::clone(&messages)),
+        ..Default::default()
+    };
+    let value = eval_source_with_options(options, "[unknown, local x = 1; x]").unwrap();
+    assert_eq!(serde_json::to_string(&value).unwrap(), "[null,1.0]");
+    assert_eq!(
+        *messages.borrow(),
+        vec!["warning: undefined variable \"unknown\" is evaluated as null".to_owned()]
+    );
+
+    assert!(matches!(
+        eval_source("unknown"),
+        Err(EvalError::UndefinedVariable(_))
+    ));
+}
+
+#[test]
+fn profile_test() {
+    let forced_thunks = |source: &str| {
+        let options = Options {
+            profile: true,
+            ..Default::default()
+        };
+        let lexer = crate::lexer::Lexer::new(source);
+        let expr = crate::syntax::ExprParser::new().parse(lexer).unwrap();
+        let env = Env::new(options);
+        eval_expr(&env, &expr).unwrap().force_deep(None).unwrap();
+        env.runtime().profile().unwrap().forced_thunks()
+    };
+
+    assert_eq!(forced_thunks("1"), 0);
+    assert_eq!(forced_thunks("[1, 2, 3]"), 3);
+    // `x` is forced twice but evaluated once.
+    assert_eq!(forced_thunks("local x = 1 + 1; [x, x, 3]"), 4);
+    // Unused elements are not forced.
+    assert_eq!(forced_thunks("[1, 2, 3][1]"), 1);
+    assert_eq!(forced_thunks("local f(x, y) = x; f(1, 2)"), 2);
+
+    let env = Env::new(Options::default());
+    assert!(env.runtime().profile().is_none());
+}
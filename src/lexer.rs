@@ -15,6 +15,12 @@ pub enum LexicalError {
 
     #[error("undefined escape: '\\{0}'")]
     UndefinedEscape(char),
+
+    #[error("unterminated triple-quoted string")]
+    UnterminatedTripleQuotedString,
+
+    #[error("integer literal cannot be represented exactly: {0}")]
+    InexactInteger(String),
 }
 
 // Success: Ok(Some((token, bytes_consumed)))
@@ -47,7 +53,13 @@ fn lex(input: &str) -> LexResult {
         return eof();
     };
     match first {
-        '.' => return ok(Token::Dot, 1),
+        '.' => {
+            return if input.starts_with("...") {
+                ok(Token::Ellipsis, 3)
+            } else {
+                ok(Token::Dot, 1)
+            };
+        }
         ':' => return ok(Token::Colon, 1),
         ';' => return ok(Token::Semicolon, 1),
         ',' => return ok(Token::Comma, 1),
@@ -63,10 +75,10 @@ fn lex(input: &str) -> LexResult {
         '/' => return ok(Token::Slash, 1),
         '%' => return ok(Token::Percent, 1),
         '=' => {
-            return if second(input) == Some('=') {
-                ok(Token::EqEq, 2)
-            } else {
-                ok(Token::Eq, 1)
+            return match second(input) {
+                Some('=') => ok(Token::EqEq, 2),
+                Some('>') => ok(Token::FatArrow, 2),
+                _ => ok(Token::Eq, 1),
             };
         }
         '!' => {
@@ -105,6 +117,8 @@ fn lex(input: &str) -> LexResult {
             "else" => Token::Else,
             "local" => Token::Local,
             "function" => Token::Function,
+            "switch" => Token::Switch,
+            "import" => Token::Import,
             _ => Token::Identifier(s.into()),
         };
         return ok(token, m.end());
@@ -121,6 +135,9 @@ fn lex(input: &str) -> LexResult {
         return ok(Token::Number(n), m.end());
     }
 
+    if input.starts_with(r#"""""#) {
+        return lex_triple_quoted_string_literal(input);
+    }
     if input.starts_with('"') {
         return lex_string_literal(input);
     }
@@ -162,11 +179,23 @@ fn lex_string_literal(input: &str) -> LexResult {
     }
 
     let bytes_consumed = input.len() - chars.as_str().len();
-    ok(Token::String(buffer.into()), bytes_consumed)
+    ok(Token::String(buffer), bytes_consumed)
 }
 
-// Same as `lex` except that it ignores leading whitespaces and comments.
-fn lex_strip(input: &str) -> LexResult {
+// A triple-quoted string spans until the next `"""`, including newlines.
+// Its content is taken verbatim; escape sequences are not processed.
+fn lex_triple_quoted_string_literal(input: &str) -> LexResult {
+    const DELIMITER: &str = r#"""""#;
+    let body = &input[DELIMITER.len()..];
+    let Some(end) = body.find(DELIMITER) else {
+        return err(LexicalError::UnterminatedTripleQuotedString);
+    };
+    let bytes_consumed = DELIMITER.len() + end + DELIMITER.len();
+    ok(Token::String(body[..end].to_owned()), bytes_consumed)
+}
+
+// Returns the number of bytes of leading whitespaces and comments in `input`.
+fn skip_whitespaces(input: &str) -> usize {
     #[rustfmt::skip]
     let re_whitespaces = static_regex!(r"(?x)^
         [\t\n\r\ ]*
@@ -177,16 +206,7 @@ fn lex_strip(input: &str) -> LexResult {
           [\t\n\r\ ]*
         )*
     ");
-    match re_whitespaces.find(input) {
-        Some(m) if !m.is_empty() => {
-            let r = lex(&input[m.end()..]);
-            match r {
-                Ok(Some((token, bytes_consumed))) => ok(token, m.end() + bytes_consumed),
-                _ => r,
-            }
-        }
-        _ => lex(input),
-    }
+    re_whitespaces.find(input).map_or(0, |m| m.end())
 }
 
 // Returns the second character of `input`.
@@ -196,9 +216,19 @@ fn second(input: &str) -> Option<char> {
     chars.next()
 }
 
+// Returns whether the number literal `literal` is represented exactly by `n`.
+// Only integer literals are checked, since most decimal fractions are inexact by nature.
+fn is_exact(literal: &str, n: f64) -> bool {
+    if literal.contains(['.', 'e', 'E']) {
+        return true;
+    }
+    format!("{n:.0}") == literal
+}
+
 pub struct Lexer<'input> {
     input: &'input str,
     bytes_consumed: usize,
+    strict_numbers: bool,
 }
 
 impl<'input> Lexer<'input> {
@@ -206,6 +236,15 @@ impl<'input> Lexer<'input> {
         Self {
             input,
             bytes_consumed: 0,
+            strict_numbers: false,
+        }
+    }
+
+    // If `strict` is true, integer literals which cannot be represented exactly are errors.
+    pub fn strict_numbers(self, strict: bool) -> Self {
+        Self {
+            strict_numbers: strict,
+            ..self
         }
     }
 }
@@ -214,11 +253,18 @@ impl<'input> Iterator for Lexer<'input> {
     type Item = Result<(usize, Token, usize), LexicalError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match lex_strip(&self.input[self.bytes_consumed..]) {
+        self.bytes_consumed += skip_whitespaces(&self.input[self.bytes_consumed..]);
+        match lex(&self.input[self.bytes_consumed..]) {
             // Success
             Ok(Some((token, bytes_consumed))) => {
                 let span_start = self.bytes_consumed;
                 let span_end = self.bytes_consumed + bytes_consumed;
+                if let Token::Number(n) = token {
+                    let literal = &self.input[span_start..span_end];
+                    if self.strict_numbers && !is_exact(literal, n) {
+                        return Some(Err(LexicalError::InexactInteger(literal.to_owned())));
+                    }
+                }
                 self.bytes_consumed = span_end;
                 Some(Ok((span_start, token, span_end)))
             }
@@ -229,3 +275,82 @@ impl<'input> Iterator for Lexer<'input> {
         }
     }
 }
+
+#[test]
+fn strict_numbers_test() {
+    let lex_all = |source: &str, strict: bool| {
+        Lexer::new(source)
+            .strict_numbers(strict)
+            .map(|r| r.map(|(_, token, _)| token))
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    let source = "12345678901234567890";
+    assert_eq!(
+        lex_all(source, false),
+        Ok(vec![Token::Number(12345678901234567890.0)])
+    );
+    assert_eq!(
+        lex_all(source, true),
+        Err(LexicalError::InexactInteger(source.to_owned()))
+    );
+
+    assert_eq!(
+        lex_all("9007199254740993", true),
+        Err(LexicalError::InexactInteger("9007199254740993".to_owned()))
+    );
+    assert_eq!(
+        lex_all("9007199254740992 0 0.1 1e100", true),
+        Ok(vec![
+            Token::Number(9007199254740992.0),
+            Token::Number(0.0),
+            Token::Number(0.1),
+            Token::Number(1e100),
+        ])
+    );
+}
+
+#[test]
+fn triple_quoted_string_test() {
+    let lex_all = |source: &str| Lexer::new(source).collect::<Result<Vec<_>, _>>();
+
+    let source = "[\"\"\"\n  SELECT \"name\"\n  FROM t WHERE x = '\\n' \"\"\n\"\"\", 1]";
+    let content = "\n  SELECT \"name\"\n  FROM t WHERE x = '\\n' \"\"\n";
+    let string_end = 1 + 3 + content.len() + 3;
+    assert_eq!(
+        lex_all(source),
+        Ok(vec![
+            (0, Token::LBracket, 1),
+            (1, Token::String(content.to_owned()), string_end),
+            (string_end, Token::Comma, string_end + 1),
+            (string_end + 2, Token::Number(1.0), string_end + 3),
+            (string_end + 3, Token::RBracket, string_end + 4),
+        ])
+    );
+
+    // Multi-byte characters are counted in bytes.
+    assert_eq!(
+        lex_all("\"\"\"α\nβ\"\"\" x"),
+        Ok(vec![
+            (0, Token::String("α\nβ".to_owned()), 11),
+            (12, Token::Identifier("x".into()), 13),
+        ])
+    );
+    assert_eq!(
+        lex_all("\"\"\"\"\"\""),
+        Ok(vec![(0, Token::String(String::new()), 6)])
+    );
+    // An ordinary empty string is not the start of a triple-quoted string.
+    assert_eq!(
+        lex_all("\"\" x"),
+        Ok(vec![
+            (0, Token::String(String::new()), 2),
+            (3, Token::Identifier("x".into()), 4),
+        ])
+    );
+
+    assert_eq!(
+        lex_all("\"\"\"\nabc\"\""),
+        Err(LexicalError::UnterminatedTripleQuotedString)
+    );
+}
@@ -1,20 +1,24 @@
 mod ast;
+mod builtins;
+mod dict;
 mod eval;
 mod lexer;
+mod random;
 mod symbol;
 mod token;
 mod types;
 mod value;
 
 use std::fs;
-use std::io::{stdin, Read};
+use std::io::{stdin, stdout, BufRead, Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use clap::Parser;
 use lalrpop_util::{lalrpop_mod, ParseError};
 use rustyline::DefaultEditor;
 
-lalrpop_mod!(pub syntax);
+lalrpop_mod!(#[allow(clippy::all)] pub syntax);
 
 #[derive(clap::Parser)]
 #[command(name = "jack")]
@@ -22,47 +26,227 @@ lalrpop_mod!(pub syntax);
 #[command(about = "A JSON Generation Language")]
 struct Cli {
     filename: Option<PathBuf>,
+
+    /// Seed for `random` and `randomInt`, which are impure.
+    /// The same seed reproduces the same output.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Directory searched by `import` after the directory of the importing file.
+    /// Can be given multiple times; directories are searched in order.
+    #[arg(short = 'I', long = "include-path")]
+    include_paths: Vec<PathBuf>,
+
+    /// Evaluate undefined variables to null with a warning, instead of failing.
+    #[arg(long)]
+    lenient: bool,
+
+    /// Bind the value of a jack expression to a variable, e.g. `--define pi=3.14159`.
+    /// Can be given multiple times; later definitions can refer to earlier ones.
+    #[arg(long = "define", value_name = "NAME=EXPR")]
+    defines: Vec<String>,
+
+    /// Order of keys in output dicts: `sorted`, `insertion`, or a comma-separated
+    /// list of keys to put first such as `name,id,*`. `*` stands for the other keys,
+    /// which are sorted; keys listed after `*` are put last.
+    #[arg(long, default_value = "sorted", value_name = "ORDER")]
+    key_order: value::KeyOrder,
+
+    /// Write reproducible output for hashing: sorted keys, no whitespace,
+    /// and integers without a fraction (e.g. `1` instead of `1.0`).
+    #[arg(long, conflicts_with = "key_order")]
+    canonical: bool,
+
+    /// Maximum number of nested arrays and dicts in the output.
+    #[arg(long, default_value_t = value::DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// Read JSON values from stdin line by line, evaluate the program for each of them
+    /// bound to `input`, and write each result as a line of JSON.
+    #[arg(long, requires = "filename")]
+    map: bool,
+
+    /// With `--map`, skip lines which are not valid JSON with a warning instead of failing.
+    #[arg(long, requires = "map")]
+    skip_errors: bool,
+
+    /// Report the number of evaluated thunks and the evaluation time to stderr.
+    #[arg(long)]
+    profile: bool,
+
+    /// Reject integer literals which cannot be represented exactly as a 64-bit float,
+    /// instead of rounding them silently.
+    #[arg(long)]
+    strict_numbers: bool,
+}
+
+impl Cli {
+    fn eval_options(&self) -> eval::Options {
+        eval::Options {
+            seed: self.seed,
+            include_paths: self.include_paths.clone(),
+            lenient: self.lenient,
+            profile: self.profile,
+            strict_numbers: self.strict_numbers,
+            key_order: self.key_order.clone(),
+            ..Default::default()
+        }
+    }
+
+    // Creates the root environment with the variables given by `--define`.
+    fn env(&self) -> anyhow::Result<eval::Env> {
+        let mut env = eval::Env::new(self.eval_options());
+        for definition in &self.defines {
+            env = define(&env, definition, |source| self.lexer(source))?;
+        }
+        Ok(env)
+    }
+
+    fn output_options(&self) -> value::OutputOptions {
+        value::OutputOptions {
+            key_order: self.key_order.clone(),
+            max_depth: self.max_depth,
+            canonical: self.canonical,
+            ..Default::default()
+        }
+    }
+
+    fn lexer<'input>(&self, source_code: &'input str) -> lexer::Lexer<'input> {
+        lexer::Lexer::new(source_code).strict_numbers(self.strict_numbers)
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
-    match cli.filename {
-        Some(filename) => execute_file(&filename),
-        None => repl(),
+    match &cli.filename {
+        Some(filename) if cli.map => execute_map(filename, &cli),
+        Some(filename) => execute_file(filename, &cli),
+        None => repl(&cli),
     }
 }
 
-fn execute_file(filename: &Path) -> anyhow::Result<()> {
+fn execute_file(filename: &Path, cli: &Cli) -> anyhow::Result<()> {
+    let (env, source_code) = read_program(filename, cli)?;
+    let lexer = cli.lexer(&source_code);
+    let parser = syntax::ExprParser::new();
+    let node = parser.parse(lexer)?;
+    let output_options = cli.output_options();
+    let start = Instant::now();
+    let value = eval_and_force(&env, &node, &output_options)
+        .map_err(|e| anyhow::anyhow!(describe_eval_error(&source_code, &e)))?;
+    report_profile(&env, start);
+    println!("{}", to_json(&value, &output_options)?);
+    Ok(())
+}
+
+// Writes the profile to stderr if `--profile` is given.
+fn report_profile(env: &eval::Env, start: Instant) {
+    if let Some(profile) = env.runtime().profile() {
+        eprintln!(
+            "profile: evaluated {} thunks in {:?}",
+            profile.forced_thunks(),
+            start.elapsed()
+        );
+    }
+}
+
+fn execute_map(filename: &Path, cli: &Cli) -> anyhow::Result<()> {
+    if filename.to_string_lossy() == "-" {
+        anyhow::bail!("--map reads input from stdin, so the program must be given as a file");
+    }
+    let (env, source_code) = read_program(filename, cli)?;
+    let node = syntax::ExprParser::new().parse(cli.lexer(&source_code))?;
+    let program = Program {
+        env: &env,
+        source_code: &source_code,
+        node: &node,
+    };
+    let start = Instant::now();
+    map_lines(
+        &program,
+        stdin().lock(),
+        stdout().lock(),
+        &cli.output_options(),
+        cli.skip_errors,
+    )?;
+    report_profile(&env, start);
+    Ok(())
+}
+
+// Reads the source code of the program and creates the environment to run it.
+fn read_program(filename: &Path, cli: &Cli) -> anyhow::Result<(eval::Env, String)> {
+    let mut env = cli.env()?;
     let source_code = if filename.to_string_lossy() == "-" {
         let mut buffer = String::new();
         stdin().read_to_string(&mut buffer)?;
         buffer
     } else {
+        if let Some(dir) = filename.parent() {
+            env = env.with_current_dir(dir);
+        }
         fs::read_to_string(filename)?
     };
-    let lexer = lexer::Lexer::new(&source_code);
-    let parser = syntax::ExprParser::new();
-    let node = parser.parse(lexer)?;
-    let env = eval::Env::new();
-    let value = eval::eval_expr(&env, &node)?;
-    println!("{}", serde_json::to_string_pretty(&value)?);
+    Ok((env, source_code))
+}
+
+struct Program<'a> {
+    env: &'a eval::Env,
+    source_code: &'a str,
+    node: &'a ast::Expr,
+}
+
+// Evaluates `program` for each line of `input` with the JSON value in the line bound to `input`,
+// and writes each result to `output` as a line of compact JSON. Lines are processed one by one,
+// so a long stream is not buffered. Blank lines are ignored.
+fn map_lines(
+    program: &Program,
+    input: impl BufRead,
+    mut output: impl Write,
+    output_options: &value::OutputOptions,
+    skip_errors: bool,
+) -> anyhow::Result<()> {
+    for (i, line) in input.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = match serde_json::from_str(&line) {
+            Ok(json) => value::Value::from_json(json),
+            Err(e) if skip_errors => {
+                let message = format!("input line {line_number} is skipped: {e}");
+                program.env.runtime().warn(&message);
+                continue;
+            }
+            Err(e) => anyhow::bail!("input line {line_number}: {e}"),
+        };
+        let thunk = std::rc::Rc::new(value::Thunk::from_value(record));
+        let env = program.env.with_variable("input".into(), thunk);
+        let value = eval_and_force(&env, program.node, output_options).map_err(|e| {
+            let description = describe_eval_error(program.source_code, &e);
+            anyhow::anyhow!("input line {line_number}: {description}")
+        })?;
+        let json = serde_json::to_string(&value.with_options(output_options))?;
+        writeln!(output, "{json}")?;
+    }
     Ok(())
 }
 
-fn repl() -> anyhow::Result<()> {
+fn repl(cli: &Cli) -> anyhow::Result<()> {
     let mut rl = rustyline::DefaultEditor::new()?;
-    let env = eval::Env::new();
+    let env = cli.env()?;
+    let output_options = cli.output_options();
 
     loop {
-        let node = repl_read_and_parse(&mut rl)?;
-        let value = match eval::eval_expr(&env, &node) {
+        let (source_code, node) = repl_read_and_parse(&mut rl, cli)?;
+        let value = match eval_and_force(&env, &node, &output_options) {
             Ok(v) => v,
             Err(e) => {
-                println!("ERROR: {e}");
+                println!("ERROR: {}", describe_eval_error(&source_code, &e));
                 continue;
             }
         };
-        let j = match serde_json::to_string_pretty(&value) {
+        let j = match to_json(&value, &output_options) {
             Ok(j) => j,
             Err(e) => {
                 println!("ERROR: {e}");
@@ -74,17 +258,20 @@ fn repl() -> anyhow::Result<()> {
     }
 }
 
-fn repl_read_and_parse(rl: &mut DefaultEditor) -> anyhow::Result<ast::Expr> {
+fn repl_read_and_parse(rl: &mut DefaultEditor, cli: &Cli) -> anyhow::Result<(String, ast::Expr)> {
     let mut prompt = "expr> ";
     let mut line = String::new();
     loop {
-        line.push_str(&rl.readline(&prompt)?);
-        let lexer = lexer::Lexer::new(&line);
+        line.push_str(&rl.readline(prompt)?);
+        let lexer = cli.lexer(&line);
         let parser = syntax::ExprParser::new();
         let expr = match parser.parse(lexer) {
             Ok(node) => node,
             Err(e) => match e {
-                ParseError::UnrecognizedEof { .. } => {
+                ParseError::UnrecognizedEof { .. }
+                | ParseError::User {
+                    error: lexer::LexicalError::UnterminatedTripleQuotedString,
+                } => {
                     line.push('\n');
                     prompt = "....| ";
                     continue;
@@ -92,8 +279,92 @@ fn repl_read_and_parse(rl: &mut DefaultEditor) -> anyhow::Result<ast::Expr> {
                 _ => Err(e)?,
             },
         };
-        return Ok(expr);
+        return Ok((line, expr));
+    }
+}
+
+// Evaluates `definition` of the form `NAME=EXPR` in `env` and binds the result to NAME.
+// The expression is evaluated eagerly, so that an error in it is reported before the script runs.
+// Errors raised later in lazy parts of the value are reported against `definition` as well.
+fn define<'a>(
+    env: &eval::Env,
+    definition: &'a str,
+    lexer: impl FnOnce(&'a str) -> lexer::Lexer<'a>,
+) -> anyhow::Result<eval::Env> {
+    let origin = format!("--define {definition:?}");
+    let Some((name, source_code)) = definition.split_once('=') else {
+        anyhow::bail!("{origin}: expected NAME=EXPR");
+    };
+    let name = name.trim();
+    // Keywords such as `if` could never be referenced.
+    let is_identifier = matches!(
+        lexer::Lexer::new(name).collect::<Vec<_>>().as_slice(),
+        [Ok((_, token::Token::Identifier(_), _))]
+    );
+    if !is_identifier {
+        anyhow::bail!("{origin}: invalid variable name: {name:?}");
     }
+    let node = syntax::ExprParser::new()
+        .parse(lexer(source_code))
+        .map_err(|e| anyhow::anyhow!("{origin}: {e}"))?;
+    let source = eval::Source {
+        name: origin,
+        code: source_code.to_owned(),
+    };
+    let value = eval::eval_expr(&env.with_source(source), &node)
+        .map_err(|e| anyhow::anyhow!(describe_eval_error(source_code, &e)))?;
+    let thunk = value::Thunk::from_value(value);
+    Ok(env.with_variable(name.into(), std::rc::Rc::new(thunk)))
+}
+
+// Evaluates `node` and forces the whole result, so that errors hidden in lazy
+// elements are reported with their locations before serialization.
+fn eval_and_force(
+    env: &eval::Env,
+    node: &ast::Expr,
+    output_options: &value::OutputOptions,
+) -> eval::Result<value::Value> {
+    let value = eval::eval_expr(env, node)?;
+    value.force_deep(Some(output_options.max_depth))?;
+    Ok(value)
+}
+
+// Canonical output is compact, since whitespace is not significant for consumers.
+fn to_json(value: &value::Value, options: &value::OutputOptions) -> serde_json::Result<String> {
+    let output = value.with_options(options);
+    if options.canonical {
+        serde_json::to_string(&output)
+    } else {
+        serde_json::to_string_pretty(&output)
+    }
+}
+
+fn describe_eval_error(source_code: &str, e: &eval::EvalError) -> String {
+    match (e.span(), e.origin()) {
+        (Some(span), Some(origin)) => {
+            let (line, column) = line_and_column(&origin.code, span.start);
+            format!("{}: line {line}, column {column}: {e}", origin.name)
+        }
+        (Some(span), None) => {
+            let (line, column) = line_and_column(source_code, span.start);
+            format!("line {line}, column {column}: {e}")
+        }
+        (None, _) => e.to_string(),
+    }
+}
+
+// Converts a byte offset into 1-origin line and column numbers.
+// An offset beyond the end is treated as the end.
+fn line_and_column(source_code: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(source_code.len());
+    while !source_code.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &source_code[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
 }
 
 #[test]
@@ -130,5 +401,172 @@ fn parse_test() {
         "{\"aaa\": 1.0, \"bbb\": 2.0}",
     );
 
-    verify("\"hello\"", "\"hello\"")
+    verify("\"hello\"", "\"hello\"");
+    verify("\"\"\"a \"b\"\n\\n\"\"\"", "\"a \\\"b\\\"\\n\\\\n\"");
+
+    verify(
+        "switch x { 1 => \"one\", _ => \"many\" }",
+        "switch x {1.0 => \"one\", _ => \"many\"}",
+    );
+
+    verify("function(x) x", "function[\"x\"] x");
+    verify("function(x, ...xs) xs", "function[\"x\", ...\"xs\"] xs");
+    verify("function(...xs,) xs", "function[...\"xs\"] xs");
+
+    verify("f(1, y: 2)", "f(1.0, y: 2.0)");
+    verify("f(...xs, 1)", "f(...xs, 1.0)");
+}
+
+#[test]
+fn define_test() {
+    let run = |defines: &[&str], source: &str| -> anyhow::Result<String> {
+        let mut env = eval::Env::new(eval::Options::default());
+        for definition in defines {
+            env = define(&env, definition, lexer::Lexer::new)?;
+        }
+        let node = syntax::ExprParser::new()
+            .parse(lexer::Lexer::new(source))
+            .unwrap();
+        let value = eval::eval_expr(&env, &node)?;
+        Ok(serde_json::to_string(&value)?)
+    };
+
+    assert_eq!(run(&["pi=3.5"], "pi * 2").unwrap(), "7.0");
+    assert_eq!(
+        run(
+            &["double=function(x) x * 2", "n = double(21)"],
+            "[n, double(1)]"
+        )
+        .unwrap(),
+        "[42.0,2.0]"
+    );
+    // Later definitions shadow earlier ones and builtins.
+    assert_eq!(
+        run(&["x=1", "x=x+1", "sum=0"], "[x, sum]").unwrap(),
+        "[2.0,0.0]"
+    );
+
+    assert_eq!(
+        run(&["pi"], "pi").unwrap_err().to_string(),
+        "--define \"pi\": expected NAME=EXPR"
+    );
+    assert_eq!(
+        run(&["1x=1"], "1").unwrap_err().to_string(),
+        "--define \"1x=1\": invalid variable name: \"1x\""
+    );
+    for keyword in [
+        "if", "then", "else", "local", "function", "switch", "import", "null", "true",
+    ] {
+        assert_eq!(
+            run(&[&format!("{keyword}=1")], "1")
+                .unwrap_err()
+                .to_string(),
+            format!("--define \"{keyword}=1\": invalid variable name: \"{keyword}\"")
+        );
+    }
+    assert!(run(&["x=1 +"], "x").is_err());
+
+    // An error in a lazy part is reported against the definition, not against the script.
+    let run_and_describe = |defines: &[&str], source: &str| {
+        let mut env = eval::Env::new(eval::Options::default());
+        for definition in defines {
+            env = define(&env, definition, lexer::Lexer::new).unwrap();
+        }
+        let node = syntax::ExprParser::new()
+            .parse(lexer::Lexer::new(source))
+            .unwrap();
+        let error = eval::eval_expr(&env, &node).unwrap_err();
+        describe_eval_error(source, &error)
+    };
+    assert_eq!(
+        run_and_describe(&[r#"d={a: 1 + "xxxxxxxx"}"#], "\n\n  d.a"),
+        r#"--define "d={a: 1 + \"xxxxxxxx\"}": line 1, column 5: bad operand type: expected=(Number + Number) or (String + String), actual=Number + String"#
+    );
+    assert_eq!(
+        run_and_describe(&["f=function(x) x.y"], "f({})"),
+        r#"--define "f=function(x) x.y": line 1, column 13: field does not exit: y"#
+    );
+
+    let cli = Cli::try_parse_from(["jack", "--define", "a=1", "--define", "b=-null"]).unwrap();
+    assert_eq!(
+        cli.env().unwrap_err().to_string(),
+        "--define \"b=-null\": line 1, column 1: bad operand type: expected=Number, actual=Null"
+    );
+}
+
+#[test]
+fn map_lines_test() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let run = |source_code: &str, input: &str, skip_errors: bool| {
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        let options = eval::Options {
+            diagnostics: eval::Diagnostics::Capture(Rc::clone(&messages)),
+            ..Default::default()
+        };
+        let env = eval::Env::new(options);
+        let node = syntax::ExprParser::new()
+            .parse(lexer::Lexer::new(source_code))
+            .unwrap();
+        let program = Program {
+            env: &env,
+            source_code,
+            node: &node,
+        };
+        let mut output = Vec::new();
+        let result = map_lines(
+            &program,
+            input.as_bytes(),
+            &mut output,
+            &value::OutputOptions::default(),
+            skip_errors,
+        );
+        let messages = messages.borrow().clone();
+        result.map(|()| (String::from_utf8(output).unwrap(), messages))
+    };
+
+    let source_code = r#"{user: input.name, level: input.level, ok: input.level != "error"}"#;
+    let input = concat!(
+        r#"{"name": "alice", "level": "info"}"#,
+        "\n",
+        "\n",
+        r#"{"level": "error", "name": "bob", "extra": [1]}"#,
+        "\n",
+        "{broken\n",
+        r#"{"name": "carol", "level": "warn"}"#,
+    );
+
+    let (output, messages) = run(source_code, input, true).unwrap();
+    assert_eq!(
+        output,
+        concat!(
+            r#"{"level":"info","ok":true,"user":"alice"}"#,
+            "\n",
+            r#"{"level":"error","ok":false,"user":"bob"}"#,
+            "\n",
+            r#"{"level":"warn","ok":true,"user":"carol"}"#,
+            "\n",
+        )
+    );
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("warning: input line 4 is skipped: "));
+
+    let error = run(source_code, input, false).unwrap_err().to_string();
+    assert!(error.starts_with("input line 4: "), "{error}");
+
+    assert_eq!(
+        run("input.name", r#"{"id": 1}"#, false)
+            .unwrap_err()
+            .to_string(),
+        "input line 1: line 1, column 1: field does not exit: name"
+    );
+}
+
+#[test]
+fn line_and_column_test() {
+    assert_eq!(line_and_column("ab\ncd", 0), (1, 1));
+    assert_eq!(line_and_column("ab\ncd", 4), (2, 2));
+    assert_eq!(line_and_column("ab\ncd", 100), (2, 3));
+    assert_eq!(line_and_column("αβ", 1), (1, 1));
 }
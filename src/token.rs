@@ -16,8 +16,11 @@ pub enum Token {
     Else,
     Local,
     Function,
+    Switch,
+    Import,
 
     Dot,
+    Ellipsis,
     Colon,
     Semicolon,
     Comma,
@@ -34,6 +37,7 @@ pub enum Token {
     Percent,
     Eq,
     EqEq,
+    FatArrow,
     Exclamation,
     NotEq,
     Ampersand,
@@ -1,13 +1,16 @@
-use std::cell::OnceCell;
+use std::cell::{OnceCell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt::{self, Debug, Formatter};
 use std::rc::Rc;
 
 use compact_str::CompactString;
 use serde::ser::{SerializeMap, SerializeSeq};
 
-use crate::ast::Expr;
+use crate::ast::{Expr, Params};
+use crate::builtins::Builtin;
+use crate::dict::Dict;
 use crate::eval::{self, Env, EvalError};
-use crate::symbol::Symbol;
 use crate::types::Erasure;
 
 #[derive(Debug, Clone, enum_assoc::Assoc)]
@@ -29,13 +32,21 @@ pub enum Value {
     Array(im_rc::Vector<Rc<Thunk>>),
 
     #[assoc(erasure = Erasure::Dict)]
-    Dict(im_rc::HashMap<CompactString, Rc<Thunk>>),
+    Dict(Dict),
 
     #[assoc(erasure = Erasure::Function)]
-    Closure(Env, Vec<Symbol>, Rc<Expr>),
+    Closure(Env, Params, Rc<Expr>),
+
+    #[assoc(erasure = Erasure::Function)]
+    Builtin(Builtin),
+
+    #[assoc(erasure = Erasure::Function)]
+    Memoized(Rc<Memoized>),
 }
 
 impl Value {
+    // Arrays and dicts are compared element by element even if they share memory, because
+    // they are not equal to themselves when they contain closures, NaN or failing elements.
     pub fn try_eq(lhs: &Value, rhs: &Value) -> eval::Result<bool> {
         match (lhs, rhs) {
             (Value::Null, Value::Null) => Ok(true),
@@ -59,7 +70,7 @@ impl Value {
                 if d1.len() != d2.len() {
                     return Ok(false);
                 }
-                for (k, v1) in d1 {
+                for (k, v1) in d1.iter() {
                     let Some(v2) = d2.get(k) else {
                         return Ok(false);
                     };
@@ -71,43 +82,390 @@ impl Value {
                 }
                 Ok(true)
             }
-            (Value::Closure(_, _, _), _) => Err(EvalError::CannotCompare),
-            (_, Value::Closure(_, _, _)) => Err(EvalError::CannotCompare),
+            (Value::Closure(_, _, _) | Value::Builtin(_) | Value::Memoized(_), _) => {
+                Err(EvalError::CannotCompare)
+            }
+            (_, Value::Closure(_, _, _) | Value::Builtin(_) | Value::Memoized(_)) => {
+                Err(EvalError::CannotCompare)
+            }
             _ => Ok(false),
         }
     }
+
+    // Orders two numbers or two strings. Strings are ordered by their bytes.
+    // Values of other types, values of different types and NaN cannot be ordered.
+    pub fn try_cmp(lhs: &Value, rhs: &Value) -> eval::Result<Ordering> {
+        match (lhs, rhs) {
+            (Value::Number(n1), Value::Number(n2)) => {
+                n1.partial_cmp(n2).ok_or(EvalError::CannotCompare)
+            }
+            (Value::String(s1), Value::String(s2)) => Ok(s1.cmp(s2)),
+            _ => Err(EvalError::CannotCompare),
+        }
+    }
+
+    // Converts parsed JSON into a value. Every number becomes a 64-bit float.
+    pub fn from_json(json: serde_json::Value) -> Value {
+        let thunk = |json| Rc::new(Thunk::from_value(Value::from_json(json)));
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::String(s) => Value::String(Rc::new(s)),
+            serde_json::Value::Array(array) => Value::Array(array.into_iter().map(thunk).collect()),
+            serde_json::Value::Object(object) => Value::Dict(
+                object
+                    .into_iter()
+                    .map(|(key, json)| (key.into(), thunk(json)))
+                    .collect(),
+            ),
+        }
+    }
+
+    // Forces every element of arrays and every value of dicts recursively,
+    // but not deeper than `max_depth` levels of nesting. Closures are left as they are.
+    // Dict values are forced in the order of their keys, so that the same error is reported
+    // every time when several values fail.
+    pub fn force_deep(&self, max_depth: Option<usize>) -> eval::Result<()> {
+        if max_depth == Some(0) {
+            return Ok(());
+        }
+        let max_depth = max_depth.map(|d| d - 1);
+        match self {
+            Value::Array(array) => {
+                for thunk in array {
+                    thunk.force()?.force_deep(max_depth)?;
+                }
+                Ok(())
+            }
+            Value::Dict(dict) => {
+                for (_, thunk) in dict.sorted() {
+                    thunk.force()?.force_deep(max_depth)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Forces `self` entirely and converts it into a `ValueKey`.
+    // Closures cannot be converted, in the same way as they cannot be compared.
+    pub fn to_key(&self) -> eval::Result<ValueKey> {
+        match self {
+            Value::Null => Ok(ValueKey::Null),
+            Value::Bool(b) => Ok(ValueKey::Bool(*b)),
+            Value::Number(n) => Ok(ValueKey::Number(canonical_bits(*n))),
+            Value::String(s) => Ok(ValueKey::String(Rc::clone(s))),
+            Value::Array(array) => {
+                let mut keys = Vec::with_capacity(array.len());
+                for thunk in array {
+                    keys.push(thunk.force()?.to_key()?);
+                }
+                Ok(ValueKey::Array(keys))
+            }
+            Value::Dict(dict) => {
+                let mut items = Vec::with_capacity(dict.len());
+                for (key, thunk) in dict.sorted() {
+                    items.push((key.clone(), thunk.force()?.to_key()?));
+                }
+                Ok(ValueKey::Dict(items))
+            }
+            Value::Closure(_, _, _) | Value::Builtin(_) | Value::Memoized(_) => {
+                Err(EvalError::CannotCompare)
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn canonical_hash(&self) -> eval::Result<u64> {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        self.to_key()?.hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+}
+
+// A fully evaluated snapshot of a `Value` which implements `Hash` and `Eq`.
+// Structurally equal values produce equal keys, so keys can be stored in a `HashMap` or `HashSet`.
+// Unlike `Value::try_eq`, every NaN is equal to each other here.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ValueKey {
+    Null,
+    Bool(bool),
+    Number(u64),
+    String(Rc<String>),
+    Array(Vec<ValueKey>),
+    Dict(Vec<(CompactString, ValueKey)>),
+}
+
+// Returns the bit pattern of `n`, identifying 0.0 with -0.0 and all NaNs with each other.
+fn canonical_bits(n: f64) -> u64 {
+    if n == 0.0 {
+        0.0f64.to_bits()
+    } else if n.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        n.to_bits()
+    }
+}
+
+// A function wrapped by `memoize`, which remembers its result for each list of arguments.
+pub struct Memoized {
+    pub func: Value,
+    cache: RefCell<HashMap<Vec<ValueKey>, Value>>,
+}
+
+impl Memoized {
+    pub fn new(func: Value) -> Self {
+        Self {
+            func,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, args: &[ValueKey]) -> Option<Value> {
+        self.cache.borrow().get(args).cloned()
+    }
+
+    pub fn insert(&self, args: Vec<ValueKey>, value: Value) {
+        self.cache.borrow_mut().insert(args, value);
+    }
+}
+
+impl Debug for Memoized {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<memoized {:?}>", self.func)
+    }
+}
+
+// The order of keys in output dicts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum KeyOrder {
+    // Keys are sorted alphabetically, which makes output stable.
+    #[default]
+    Sorted,
+    // Keys appear in the order in which they were inserted.
+    Insertion,
+    // The listed keys come first and last in the given order, and the other keys are sorted
+    // alphabetically in between.
+    Priority {
+        first: Vec<CompactString>,
+        last: Vec<CompactString>,
+    },
+}
+
+impl KeyOrder {
+    // Returns the entries of `dict` in this order.
+    pub fn arrange<'a>(&self, dict: &'a Dict) -> Vec<(&'a CompactString, &'a Rc<Thunk>)> {
+        match self {
+            KeyOrder::Sorted => dict.sorted(),
+            KeyOrder::Insertion => dict.iter().collect(),
+            KeyOrder::Priority { .. } => {
+                let mut items = dict.sorted();
+                items.sort_by_key(|(key, _)| self.priority(key));
+                items
+            }
+        }
+    }
+
+    // Returns the position of `key` as (group, index in the group).
+    // Keys in the same group with the same index are sorted alphabetically.
+    fn priority(&self, key: &str) -> (usize, usize) {
+        let KeyOrder::Priority { first, last } = self else {
+            return (1, 0);
+        };
+        if let Some(i) = first.iter().position(|k| k.as_str() == key) {
+            (0, i)
+        } else if let Some(i) = last.iter().position(|k| k.as_str() == key) {
+            (2, i)
+        } else {
+            (1, 0)
+        }
+    }
+}
+
+// Parses `sorted`, `insertion` or a comma-separated list of keys such as `name,id,*,note`.
+// `*` stands for the keys which are not listed; it is at the end if omitted.
+impl std::str::FromStr for KeyOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sorted" => return Ok(KeyOrder::Sorted),
+            "insertion" => return Ok(KeyOrder::Insertion),
+            _ => {}
+        }
+        let mut first = Vec::new();
+        let mut last = Vec::new();
+        let mut seen_rest = false;
+        for key in s.split(',') {
+            match key {
+                "*" if seen_rest => return Err("`*` appears more than once".to_owned()),
+                "*" => seen_rest = true,
+                "" => return Err("empty key in the list".to_owned()),
+                key if seen_rest => last.push(key.into()),
+                key => first.push(key.into()),
+            }
+        }
+        Ok(KeyOrder::Priority { first, last })
+    }
+}
+
+// The default of `OutputOptions::max_depth`. Deeper values would overflow the stack
+// while being forced or serialized.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub key_order: KeyOrder,
+    // The maximum number of nested arrays and dicts.
+    pub max_depth: usize,
+    // If true, functions and arrays and dicts deeper than `max_depth` are written as
+    // placeholder strings instead of failing.
+    pub placeholders: bool,
+    // If true, elements are not evaluated, and those not evaluated yet are written as
+    // `<unevaluated>`. This shows how far the lazy evaluation has reached.
+    pub unevaluated_placeholder: bool,
+    // If true, the output is made reproducible byte for byte. See `Output::serialize_number`.
+    pub canonical: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            key_order: KeyOrder::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            placeholders: false,
+            unevaluated_placeholder: false,
+            canonical: false,
+        }
+    }
+}
+
+// A `Value` bundled with `OutputOptions`, since `Serialize::serialize` takes no extra arguments.
+pub struct Output<'a> {
+    value: &'a Value,
+    options: &'a OutputOptions,
+    depth: usize,
+}
+
+impl Value {
+    pub fn with_options<'a>(&'a self, options: &'a OutputOptions) -> Output<'a> {
+        Output {
+            value: self,
+            options,
+            depth: 0,
+        }
+    }
+}
+
+impl<'a> Output<'a> {
+    fn child(&self, value: &'a Value) -> Output<'a> {
+        Output {
+            value,
+            options: self.options,
+            depth: self.depth + 1,
+        }
+    }
+
+    // Canonical output always sorts keys, regardless of `key_order`.
+    fn key_order(&self) -> &KeyOrder {
+        if self.options.canonical {
+            &KeyOrder::Sorted
+        } else {
+            &self.options.key_order
+        }
+    }
+
+    // In canonical mode, integers whose magnitude is less than 2^53 are written without
+    // a fraction or an exponent (e.g. `1`, `-20`), and -0 is written as `0`.
+    // Other finite numbers are written in the shortest form which reads back as the same
+    // 64-bit float (e.g. `0.1`, `1e300`, `9007199254740992.0`).
+    // Non-finite numbers are written as `null`, as in the normal mode.
+    fn serialize_number<S: serde::Serializer>(
+        &self,
+        n: f64,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        const EXACT_LIMIT: f64 = (1u64 << 53) as f64;
+        if self.options.canonical && n.fract() == 0.0 && n.abs() < EXACT_LIMIT {
+            serializer.serialize_i64(n as i64)
+        } else {
+            serializer.serialize_f64(n)
+        }
+    }
+
+    fn force<E: serde::ser::Error>(&self, thunk: &Thunk) -> Result<Value, E> {
+        if self.options.unevaluated_placeholder {
+            let placeholder = || Value::String(Rc::new("<unevaluated>".to_owned()));
+            return Ok(thunk.evaluated().unwrap_or_else(placeholder));
+        }
+        thunk.force().map_err(|e| E::custom(e.to_string()))
+    }
+
+    fn check_depth<E: serde::ser::Error>(&self) -> Result<(), E> {
+        if self.depth >= self.options.max_depth {
+            return Err(E::custom("maximum nesting depth exceeded"));
+        }
+        Ok(())
+    }
 }
 
 impl serde::Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.with_options(&OutputOptions::default())
+            .serialize(serializer)
+    }
+}
+
+impl<'a> serde::Serialize for Output<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
         use serde::ser::Error;
-        match self {
+        match self.value {
             Value::Null => serializer.serialize_none(),
             Value::Bool(b) => serializer.serialize_bool(*b),
-            Value::Number(n) => serializer.serialize_f64(*n),
-            Value::String(s) => serializer.serialize_str(&*s),
+            Value::Number(n) => self.serialize_number(*n, serializer),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Array(_) | Value::Dict(_)
+                if self.options.placeholders && self.depth >= self.options.max_depth =>
+            {
+                serializer.serialize_str("<...>")
+            }
             Value::Array(array) => {
+                self.check_depth()?;
                 let mut seq = serializer.serialize_seq(Some(array.len()))?;
                 for thunk in array {
-                    let value = thunk.force().map_err(|e| Error::custom(e.to_string()))?;
-                    seq.serialize_element(&value)?;
+                    let value = self.force(thunk)?;
+                    seq.serialize_element(&self.child(&value))?;
                 }
                 seq.end()
             }
             Value::Dict(dict) => {
+                self.check_depth()?;
                 let mut map = serializer.serialize_map(Some(dict.len()))?;
-                let mut items: Vec<(_, _)> = dict.iter().collect();
-                items.sort_unstable_by(|(k1, _), (k2, _)| k1.cmp(k2));
-                for (key, thunk) in items {
-                    let value = thunk.force().map_err(|e| Error::custom(e.to_string()))?;
-                    map.serialize_entry(key, &value)?;
+                for (key, thunk) in self.key_order().arrange(dict) {
+                    let value = self.force(thunk)?;
+                    map.serialize_entry(key, &self.child(&value))?;
                 }
                 map.end()
             }
-            Value::Closure(_, _, _) => Err(Error::custom("closure is not serializable")),
+            Value::Closure(_, _, _) | Value::Builtin(_) | Value::Memoized(_)
+                if self.options.placeholders =>
+            {
+                serializer.serialize_str("<function>")
+            }
+            Value::Closure(_, _, _) | Value::Memoized(_) => {
+                Err(Error::custom("closure is not serializable"))
+            }
+            Value::Builtin(_) => Err(Error::custom("builtin function is not serializable")),
         }
     }
 }
@@ -127,6 +485,14 @@ impl Thunk {
         }
     }
 
+    pub fn from_value(value: Value) -> Self {
+        Self {
+            env: OnceCell::new(),
+            expr: Box::new(Expr::Null),
+            value: OnceCell::from(value),
+        }
+    }
+
     pub fn partial_new(expr: Box<Expr>) -> Self {
         Self {
             env: OnceCell::new(),
@@ -139,6 +505,11 @@ impl Thunk {
         let _ = self.env.set(env);
     }
 
+    // Returns the value if it has been evaluated, without evaluating it.
+    pub fn evaluated(&self) -> Option<Value> {
+        self.value.get().cloned()
+    }
+
     pub fn force(&self) -> eval::Result<Value> {
         if let Some(v) = self.value.get() {
             return Ok(v.clone());
@@ -146,6 +517,9 @@ impl Thunk {
         let Some(env) = self.env.get() else {
             panic!("env is not set")
         };
+        if let Some(profile) = env.runtime().profile() {
+            profile.count_force();
+        }
         let v = eval::eval_expr(env, &self.expr)?;
         let _ = self.value.set(v.clone());
         Ok(v)
@@ -157,3 +531,218 @@ impl Debug for Thunk {
         write!(f, "{:?}", self.force())
     }
 }
+
+#[test]
+fn canonical_hash_test() {
+    use crate::eval::eval_source;
+
+    let hash = |source: &str| eval_source(source).unwrap().canonical_hash().unwrap();
+
+    assert_eq!(hash("[1, \"a\", null]"), hash("[1, \"a\", null]"));
+    assert_eq!(hash("{a: 1, b: [true]}"), hash("{b: [true], a: 1}"));
+    assert_eq!(hash("0"), hash("-0"));
+    assert_ne!(hash("[1, 2]"), hash("[2, 1]"));
+    assert_ne!(hash("{a: 1}"), hash("{a: 2}"));
+    assert_ne!(hash("\"1\""), hash("1"));
+    assert_ne!(hash("[]"), hash("{}"));
+
+    let try_hash = |source: &str| eval_source(source).unwrap().canonical_hash();
+    assert!(matches!(
+        try_hash("function(x) x"),
+        Err(EvalError::CannotCompare)
+    ));
+    assert!(matches!(
+        try_hash("[1, function(x) x]"),
+        Err(EvalError::CannotCompare)
+    ));
+}
+
+#[test]
+fn try_eq_test() {
+    use crate::eval::eval_to_json;
+
+    let verify = |source: &str, expected: &str| {
+        assert_eq!(eval_to_json(source), Ok(expected.to_owned()));
+    };
+
+    verify("[1, [2, {a: 3}]] == [1, [2, {a: 3}]]", "true");
+    verify("[1, [2, {a: 3}]] == [1, [2, {a: 4}]]", "false");
+    verify("{a: 1, b: 2} == {b: 2, a: 1}", "true");
+    verify("{a: 1} == {a: 1, b: 2}", "false");
+    verify("\"foo\" == \"foo\"", "true");
+    verify("local xs = [1, 2, 3]; xs == xs", "true");
+    verify("local d = {a: [1]}; d == d", "true");
+    verify("local s = \"foo\"; s == s", "true");
+
+    assert_eq!(
+        eval_to_json("[function(x) x] == [function(x) x]"),
+        Err("cannot compare".to_owned())
+    );
+
+    // Comparing a value with itself gives the same result as comparing it with a copy.
+    assert_eq!(
+        eval_to_json("local d = {f: function(x) x}; d == d"),
+        Err("cannot compare".to_owned())
+    );
+    assert!(eval_to_json("local e = {x: 1 + \"a\"}; e == e").is_err());
+    verify("local xs = [0 / 0]; xs == xs", "false");
+    verify(
+        "local xs = concat([0 / 0], replicate(100, 1)); xs == xs",
+        "false",
+    );
+}
+
+#[test]
+fn key_order_test() {
+    use crate::eval::eval_source;
+
+    let value = eval_source("{b: 1, c: {z: 1, y: 2}, a: 3, b: 4}").unwrap();
+
+    let options = OutputOptions::default();
+    assert_eq!(
+        serde_json::to_string(&value.with_options(&options)).unwrap(),
+        r#"{"a":3.0,"b":4.0,"c":{"y":2.0,"z":1.0}}"#
+    );
+
+    let options = OutputOptions {
+        key_order: KeyOrder::Insertion,
+        ..Default::default()
+    };
+    assert_eq!(
+        serde_json::to_string(&value.with_options(&options)).unwrap(),
+        r#"{"b":4.0,"c":{"z":1.0,"y":2.0},"a":3.0}"#
+    );
+}
+
+#[test]
+fn max_depth_test() {
+    use crate::eval::eval_source;
+
+    let options = OutputOptions {
+        max_depth: 3,
+        ..Default::default()
+    };
+    let serialize = |source: &str| {
+        let value = eval_source(source).unwrap();
+        serde_json::to_string(&value.with_options(&options)).map_err(|e| e.to_string())
+    };
+    let nest =
+        |n: usize| format!("local nest(n) = if n == 0 then 0 else [{{a: nest(n - 1)}}]; nest({n})");
+
+    assert_eq!(serialize("1"), Ok("1.0".to_owned()));
+    assert_eq!(serialize(&nest(1)), Ok(r#"[{"a":0.0}]"#.to_owned()));
+    assert_eq!(
+        serialize(&nest(2)),
+        Err("maximum nesting depth exceeded".to_owned())
+    );
+    assert_eq!(
+        serialize(&nest(1000)),
+        Err("maximum nesting depth exceeded".to_owned())
+    );
+
+    // The depth is limited by default, so that a deep value does not overflow the stack.
+    // The check runs with the stack size of the main thread, which is larger than that of tests.
+    let source = nest(DEFAULT_MAX_DEPTH);
+    let result = std::thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(move || {
+            let value = eval_source(&source).unwrap();
+            serde_json::to_string(&value).map_err(|e| e.to_string())
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(result, Err("maximum nesting depth exceeded".to_owned()));
+}
+
+#[test]
+fn canonical_test() {
+    use crate::eval::eval_source;
+
+    let options = OutputOptions {
+        canonical: true,
+        key_order: KeyOrder::Insertion,
+        ..Default::default()
+    };
+    let serialize = |source: &str| {
+        let value = eval_source(source).unwrap();
+        serde_json::to_string(&value.with_options(&options)).unwrap()
+    };
+
+    let source =
+        r#"{z: [1, -0, 2.5, 0.1, 1e300, 9007199254740993], a: {y: true, b: null}, m: "x"}"#;
+    let expected =
+        r#"{"a":{"b":null,"y":true},"m":"x","z":[1,0,2.5,0.1,1e300,9007199254740992.0]}"#;
+    assert_eq!(serialize(source), expected);
+    // Evaluating and serializing again produces the same bytes.
+    assert_eq!(serialize(source), serialize(source));
+    assert_eq!(
+        serialize(
+            r#"{m: "x", a: {b: null, y: true}, z: [1, 0, 2.5, 0.1, 1e300, 9007199254740992]}"#
+        ),
+        expected
+    );
+}
+
+#[test]
+fn escaped_key_test() {
+    use crate::eval::eval_source;
+
+    let source = "{\"a\\\"b\": 1, \"line\\nbreak\": 2, \"back\\\\slash\": 3, \"ctl\u{1}\": 4, \"a\\\"b\": 5}";
+    let value = eval_source(source).unwrap();
+    let expected = r#"{"a\"b":5.0,"back\\slash":3.0,"ctl\u0001":4.0,"line\nbreak":2.0}"#;
+    assert_eq!(serde_json::to_string(&value).unwrap(), expected);
+
+    // The duplicate key keeps the position of its first occurrence and the last value.
+    let options = OutputOptions {
+        key_order: KeyOrder::Insertion,
+        ..Default::default()
+    };
+    let output = serde_json::to_string(&value.with_options(&options)).unwrap();
+    assert_eq!(
+        output,
+        r#"{"a\"b":5.0,"line\nbreak":2.0,"back\\slash":3.0,"ctl\u0001":4.0}"#
+    );
+    let parsed: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(parsed["ctl\u{1}"], 4.0);
+
+    // Keys computed at runtime are escaped in the same way.
+    let value = eval_source(r#"fromEntries([["q\"", 1], ["t\t", 2], ["q\"", 3]])"#).unwrap();
+    assert_eq!(
+        serde_json::to_string(&value).unwrap(),
+        r#"{"q\"":3.0,"t\t":2.0}"#
+    );
+}
+
+#[test]
+fn priority_key_order_test() {
+    use crate::eval::eval_source;
+
+    let value =
+        eval_source(r#"{note: 1, id: 2, b: 3, name: 4, a: {id: 5, z: 6, name: 7}}"#).unwrap();
+    let serialize = |key_order: &str| {
+        let options = OutputOptions {
+            key_order: key_order.parse().unwrap(),
+            ..Default::default()
+        };
+        serde_json::to_string(&value.with_options(&options)).unwrap()
+    };
+
+    assert_eq!(
+        serialize("name,id,*"),
+        r#"{"name":4.0,"id":2.0,"a":{"name":7.0,"id":5.0,"z":6.0},"b":3.0,"note":1.0}"#
+    );
+    assert_eq!(serialize("name,id"), serialize("name,id,*"));
+    assert_eq!(
+        serialize("id,*,note,a"),
+        r#"{"id":2.0,"b":3.0,"name":4.0,"note":1.0,"a":{"id":5.0,"name":7.0,"z":6.0}}"#
+    );
+    assert_eq!(
+        serialize("sorted"),
+        r#"{"a":{"id":5.0,"name":7.0,"z":6.0},"b":3.0,"id":2.0,"name":4.0,"note":1.0}"#
+    );
+    assert_eq!(serialize("sorted"), serialize("*"));
+
+    assert!("a,*,b,*".parse::<KeyOrder>().is_err());
+    assert!("a,,b".parse::<KeyOrder>().is_err());
+}
@@ -0,0 +1,86 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+// Writes `program` to a temporary file and runs `jack --map` on it with `input` as stdin.
+fn run_map(name: &str, program: &str, args: &[&str], input: &str) -> Output {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.jack"));
+    std::fs::write(&path, program).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jack"))
+        .arg("--map")
+        .args(args)
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn map_test() {
+    let output = run_map(
+        "map_test",
+        "{id: input.id, double: input.n * 2}",
+        &[],
+        "{\"id\": \"a\", \"n\": 1}\n\n{\"id\": \"b\", \"n\": 2.5}\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"double\":2.0,\"id\":\"a\"}\n{\"double\":5.0,\"id\":\"b\"}\n"
+    );
+}
+
+#[test]
+fn map_key_order_test() {
+    // The order of keys in the input is kept.
+    let output = run_map(
+        "map_key_order_test",
+        "input",
+        &["--key-order", "insertion"],
+        "{\"b\": 1, \"a\": 2}\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"b\":1.0,\"a\":2.0}\n"
+    );
+}
+
+#[test]
+fn map_skip_errors_test() {
+    let input = "1\nnot json\n2\n";
+
+    let output = run_map(
+        "map_skip_errors_test",
+        "input + 1",
+        &["--skip-errors"],
+        input,
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2.0\n3.0\n");
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("input line 2 is skipped"));
+
+    let output = run_map("map_skip_errors_test", "input + 1", &[], input);
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2.0\n");
+}
+
+#[test]
+fn map_profile_test() {
+    let output = run_map("map_profile_test", "input", &["--profile"], "1\n2\n");
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("profile: evaluated "));
+}